@@ -0,0 +1,149 @@
+//! Serde codecs shared by the guest's `TypedIpc` and exports and the host's `RpcServer` and
+//! `call_export`, enabled with the `serde_json`, `bincode` and `postcard` features. Both sides
+//! check that the other one uses the same codec before decoding anything.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+
+/// Request id of the frame a `TypedIpc` sends ahead of its first call, its payload is the
+/// [`Codec::ID`] of the guest's codec as a little endian `u32`
+pub const HANDSHAKE_ID: u64 = u64::MAX;
+
+pub trait Codec {
+    /// Tells the codecs apart in handshakes, see [`check_codec`]
+    const ID: u32;
+
+    /// Appends the encoded `value` to `buf`
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), CodecError>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+pub struct CodecError(Box<dyn Error + Send + Sync>);
+
+impl CodecError {
+    pub fn new(err: impl Error + Send + Sync + 'static) -> Self {
+        Self(Box::new(err))
+    }
+}
+
+impl Debug for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "codec error: {}", self.0)
+    }
+}
+
+impl Error for CodecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// The other side of a channel or export encodes with another codec
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CodecMismatch {
+    pub local: u32,
+    pub peer: u32,
+}
+
+/// Fails with [`CodecMismatch`] unless `peer` is the [`Codec::ID`] of `C`
+pub fn check_codec<C: Codec>(peer: u32) -> Result<(), CodecError> {
+    if peer == C::ID {
+        Ok(())
+    } else {
+        Err(CodecError::new(CodecMismatch {
+            local: C::ID,
+            peer,
+        }))
+    }
+}
+
+fn codec_name(id: u32) -> &'static str {
+    match id {
+        1 => "json",
+        2 => "bincode",
+        3 => "postcard",
+        _ => "unknown",
+    }
+}
+
+impl Display for CodecMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peer encodes with {} (id {}) but {} (id {}) is expected, both sides have to be built \
+             with the same codec feature",
+            codec_name(self.peer),
+            self.peer,
+            codec_name(self.local),
+            self.local,
+        )
+    }
+}
+
+impl Error for CodecMismatch {}
+
+#[cfg(feature = "serde_json")]
+pub struct Json;
+
+#[cfg(feature = "serde_json")]
+impl Codec for Json {
+    const ID: u32 = 1;
+
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), CodecError> {
+        serde_json::to_writer(buf, value).map_err(CodecError::new)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(CodecError::new)
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    const ID: u32 = 2;
+
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), CodecError> {
+        bincode::serialize_into(buf, value).map_err(CodecError::new)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        bincode::deserialize(bytes).map_err(CodecError::new)
+    }
+}
+
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    const ID: u32 = 3;
+
+    fn encode<T: Serialize>(value: &T, buf: &mut Vec<u8>) -> Result<(), CodecError> {
+        buf.extend(postcard::to_allocvec(value).map_err(CodecError::new)?);
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        postcard::from_bytes(bytes).map_err(CodecError::new)
+    }
+}
+
+/// The first enabled of bincode, postcard and json, so enabling one of the compact codecs
+/// overrides the default json
+#[cfg(feature = "bincode")]
+pub type DefaultCodec = Bincode;
+#[cfg(all(feature = "postcard", not(feature = "bincode")))]
+pub type DefaultCodec = Postcard;
+#[cfg(all(feature = "serde_json", not(any(feature = "bincode", feature = "postcard"))))]
+pub type DefaultCodec = Json;
@@ -0,0 +1,228 @@
+use crate::wasi_api::{GuestPanic, Panicked};
+use gimli::{ColumnType, Dwarf, EndianSlice, LittleEndian};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use wasmer::wasmparser::{Name, NameSectionReader, Parser, Payload};
+use wasmer::RuntimeError;
+
+/// Source locations of a module's code, read from its DWARF sections, and the function names of
+/// its `name` section
+#[derive(Debug, Default)]
+pub struct Symbols {
+    /// DWARF addresses are offsets into the contents of the code section
+    code_offset: usize,
+    /// sorted by address, a row holds until the next one
+    rows: Vec<Row>,
+    files: Vec<String>,
+    /// by function index in the original module
+    functions: HashMap<u32, String>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Row {
+    address: u64,
+    /// `None` past the end of a sequence
+    file: Option<u32>,
+    line: u32,
+    column: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Formats a trap with the guest's panic message and a symbolized backtrace
+pub struct Backtrace<'a> {
+    /// the trap, unwrapped from [`Panicked`]
+    error: RuntimeError,
+    symbols: Option<&'a Symbols>,
+    panic: Option<GuestPanic>,
+}
+
+impl Symbols {
+    /// Reads the line tables of `wasm`, a module without debug info gets empty ones
+    pub fn parse(wasm: &[u8]) -> Self {
+        let mut code_offset = 0;
+        let mut sections = HashMap::new();
+        let mut functions = HashMap::new();
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload {
+                Ok(Payload::CodeSectionStart { range, .. }) => code_offset = range.start,
+                Ok(Payload::CustomSection {
+                    name: "name",
+                    data,
+                    data_offset,
+                    ..
+                }) => {
+                    if let Err(err) = read_function_names(data, data_offset, &mut functions) {
+                        log::debug!("failed to read the module's name section: {}", err);
+                    }
+                }
+                Ok(Payload::CustomSection { name, data, .. }) if name.starts_with(".debug_") => {
+                    sections.insert(name, data);
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+
+        let mut symbols = Self {
+            code_offset,
+            functions,
+            ..Self::default()
+        };
+        if let Err(err) = symbols.read_dwarf(&sections) {
+            log::debug!("failed to read the module's debug info: {}", err);
+            symbols.rows.clear();
+        }
+        symbols.rows.sort_by_key(|row| row.address);
+        symbols
+    }
+
+    fn read_dwarf(&mut self, sections: &HashMap<&str, &[u8]>) -> Result<(), gimli::Error> {
+        let dwarf = Dwarf::load(|id| -> Result<_, gimli::Error> {
+            let data = sections.get(id.name()).copied().unwrap_or(&[]);
+            Ok(EndianSlice::new(data, LittleEndian))
+        })?;
+        let mut file_ids = HashMap::new();
+
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let program = match unit.line_program.clone() {
+                Some(program) => program,
+                None => continue,
+            };
+
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() {
+                    self.rows.push(Row {
+                        address: row.address(),
+                        file: None,
+                        line: 0,
+                        column: 0,
+                    });
+                    continue;
+                }
+
+                let mut path = PathBuf::new();
+                if let Some(file) = row.file(header) {
+                    if let Some(dir) = &unit.comp_dir {
+                        path.push(&*dir.to_string_lossy());
+                    }
+                    if let Some(dir) = file.directory(header) {
+                        path.push(&*dwarf.attr_string(&unit, dir)?.to_string_lossy());
+                    }
+                    path.push(
+                        &*dwarf
+                            .attr_string(&unit, file.path_name())?
+                            .to_string_lossy(),
+                    );
+                }
+                let path = path.to_string_lossy().into_owned();
+                let files = &mut self.files;
+                let file = *file_ids.entry(path).or_insert_with_key(|path| {
+                    files.push(path.clone());
+                    files.len() as u32 - 1
+                });
+
+                self.rows.push(Row {
+                    address: row.address(),
+                    file: Some(file),
+                    line: row.line().map_or(0, |line| line.get() as u32),
+                    column: match row.column() {
+                        ColumnType::LeftEdge => 0,
+                        ColumnType::Column(column) => column.get() as u32,
+                    },
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Mangled name of the function with `index` in the original module
+    pub fn function_name(&self, index: u32) -> Option<&str> {
+        self.functions.get(&index).map(String::as_str)
+    }
+
+    /// Where the instruction at `module_offset` in the wasm binary came from
+    pub fn location(&self, module_offset: usize) -> Option<Location<'_>> {
+        let address = module_offset.checked_sub(self.code_offset)? as u64;
+        let index = self.rows.partition_point(|row| row.address <= address);
+        let row = self.rows[..index].last()?;
+        Some(Location {
+            file: &self.files[row.file? as usize],
+            line: row.line,
+            column: row.column,
+        })
+    }
+}
+
+fn read_function_names(
+    data: &[u8],
+    offset: usize,
+    functions: &mut HashMap<u32, String>,
+) -> Result<(), wasmer::wasmparser::BinaryReaderError> {
+    let mut names = NameSectionReader::new(data, offset)?;
+    while !names.eof() {
+        if let Name::Function(map) = names.read()? {
+            let mut map = map.get_map()?;
+            for _ in 0..map.get_count() {
+                let naming = map.read()?;
+                functions.insert(naming.index, naming.name.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<'a> Backtrace<'a> {
+    pub fn new(error: &RuntimeError, symbols: Option<&'a Symbols>) -> Self {
+        // `downcast` only works on the last clone of the error, the caller holds on to it
+        let panicked = std::error::Error::source(error).and_then(|err| err.downcast_ref::<Panicked>());
+        let (error, panic) = match panicked {
+            Some(Panicked { panic, trap }) => (trap.clone(), Some(panic.clone())),
+            None => (error.clone(), None),
+        };
+        Self {
+            error,
+            symbols,
+            panic,
+        }
+    }
+}
+
+impl Display for Backtrace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.error.message())?;
+        if let Some(panic) = &self.panic {
+            writeln!(f, "guest {}", panic)?;
+        }
+
+        writeln!(f, "wasm backtrace:")?;
+        for (i, frame) in self.error.trace().iter().enumerate() {
+            match frame.function_name() {
+                Some(name) => writeln!(f, "{:>4}: {:#}", i, rustc_demangle::demangle(name))?,
+                None => writeln!(
+                    f,
+                    "{:>4}: <{}>!<wasm function {}>",
+                    i,
+                    frame.module_name(),
+                    frame.func_index()
+                )?,
+            }
+            let location = self
+                .symbols
+                .and_then(|symbols| symbols.location(frame.module_offset()));
+            if let Some(Location { file, line, column }) = location {
+                writeln!(f, "              at {}:{}:{}", file, line, column)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::path::PathBuf;
+use wasmer::{CompileError, Module, Store};
+
+/// On-disk cache of compiled modules.
+///
+/// Entries are keyed by the wasm bytes and a description of everything else that changes the
+/// compiled artifact: the wasmer version, the compiler and its settings and the
+/// `ModuleTransformer` configuration.
+pub struct ModuleCache {
+    dir: PathBuf,
+    settings: String,
+}
+
+impl ModuleCache {
+    pub fn new(dir: impl Into<PathBuf>, settings: impl Into<String>) -> Self {
+        Self {
+            dir: dir.into(),
+            settings: settings.into(),
+        }
+    }
+
+    /// Uses `WASSUP_CACHE_DIR`, falling back to `target/wassup-cache`
+    pub fn from_env(settings: impl Into<String>) -> Self {
+        let dir = std::env::var_os("WASSUP_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("target/wassup-cache"));
+        Self::new(dir, settings)
+    }
+
+    pub fn key(&self, wasm: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(wasmer::VERSION.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.settings.as_bytes());
+        hasher.update([0]);
+        hasher.update(wasm);
+
+        let mut key = String::with_capacity(64);
+        for byte in hasher.finalize() {
+            let _ = write!(key, "{:02x}", byte);
+        }
+        key
+    }
+
+    pub fn path(&self, wasm: &[u8]) -> PathBuf {
+        self.dir.join(format!("{}.wasmu", self.key(wasm)))
+    }
+
+    /// Loads the compiled module from the cache, compiles and stores it on a miss
+    pub fn load(&self, store: &Store, wasm: &[u8]) -> Result<Module, CompileError> {
+        let path = self.path(wasm);
+        if path.exists() {
+            // SAFETY: the cache directory only contains artifacts serialized by `store` below,
+            // and the key covers every setting the artifact depends on
+            match unsafe { Module::deserialize_from_file(store, &path) } {
+                Ok(module) => return Ok(module),
+                Err(err) => log::warn!("discarding cached module {}: {}", path.display(), err),
+            }
+        }
+
+        self.compile(store, wasm)
+    }
+
+    /// Compiles the module and stores it in the cache, replacing an existing entry
+    pub fn compile(&self, store: &Store, wasm: &[u8]) -> Result<Module, CompileError> {
+        let module = Module::new(store, wasm)?;
+        if let Err(err) = self.store(wasm, &module) {
+            log::warn!("failed to cache compiled module: {}", err);
+        }
+        Ok(module)
+    }
+
+    fn store(&self, wasm: &[u8], module: &Module) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        // write to a temporary file first, so a concurrent load never sees a partial artifact
+        let path = self.path(wasm);
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        module.serialize_to_file(&tmp)?;
+        std::fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use crate::memory_limit::LimitingTunables;
+use crate::transformer::ModuleTransformer;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::Arc;
+use wasmer::{BaseTunables, CompilerConfig, Store, Target};
+use wasmer_engine_universal::Universal;
+
+#[cfg(not(any(feature = "llvm", feature = "cranelift", feature = "singlepass")))]
+compile_error!("at least one of the `llvm`, `cranelift` or `singlepass` features must be enabled");
+
+/// Compilers this build supports, selected through cargo features
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// Slow to compile, fastest code
+    #[cfg(feature = "llvm")]
+    Llvm,
+    #[cfg(feature = "cranelift")]
+    Cranelift,
+    /// Fastest to compile, slowest code
+    #[cfg(feature = "singlepass")]
+    Singlepass,
+}
+
+impl Backend {
+    pub const ALL: &'static [Backend] = &[
+        #[cfg(feature = "llvm")]
+        Backend::Llvm,
+        #[cfg(feature = "cranelift")]
+        Backend::Cranelift,
+        #[cfg(feature = "singlepass")]
+        Backend::Singlepass,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => "llvm",
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => "cranelift",
+            #[cfg(feature = "singlepass")]
+            Backend::Singlepass => "singlepass",
+        }
+    }
+
+    /// Describes the compiler and its settings, used as part of the module cache key
+    pub fn settings(self) -> &'static str {
+        match self {
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => "llvm opt=aggressive",
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => "cranelift opt=speed",
+            #[cfg(feature = "singlepass")]
+            Backend::Singlepass => "singlepass",
+        }
+    }
+
+    /// Creates a store compiling with this backend, `transformer` runs on every function. Its
+    /// instances honor [`InstanceBuilder::memory_limit`](crate::InstanceBuilder::memory_limit).
+    pub fn make_store(self, transformer: Arc<ModuleTransformer>) -> Store {
+        let mut config: Box<dyn CompilerConfig> = match self {
+            #[cfg(feature = "llvm")]
+            Backend::Llvm => {
+                use wasmer_compiler_llvm::{LLVMOptLevel, LLVM};
+                let mut compiler = LLVM::default();
+                compiler.opt_level(LLVMOptLevel::Aggressive);
+                Box::new(compiler)
+            }
+            #[cfg(feature = "cranelift")]
+            Backend::Cranelift => {
+                use wasmer_compiler_cranelift::{Cranelift, CraneliftOptLevel};
+                let mut compiler = Cranelift::default();
+                compiler.opt_level(CraneliftOptLevel::Speed);
+                Box::new(compiler)
+            }
+            #[cfg(feature = "singlepass")]
+            Backend::Singlepass => Box::new(wasmer_compiler_singlepass::Singlepass::default()),
+        };
+        config.push_middleware(transformer);
+
+        let tunables = LimitingTunables::new(BaseTunables::for_target(&Target::default()));
+        Store::new_with_tunables(&Universal::new(config).engine(), tunables)
+    }
+}
+
+impl Default for Backend {
+    /// The first enabled of llvm, cranelift and singlepass
+    fn default() -> Self {
+        Backend::ALL[0]
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Backend::ALL
+            .iter()
+            .copied()
+            .find(|backend| backend.name() == s)
+            .ok_or_else(|| {
+                let names = Backend::ALL.iter().map(|b| b.name()).collect::<Vec<_>>();
+                format!("unknown or disabled compiler `{}`, available: {}", s, names.join(", "))
+            })
+    }
+}
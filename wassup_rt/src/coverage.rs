@@ -0,0 +1,167 @@
+use crate::backtrace::{Location, Symbols};
+use crate::transformer::{depth_after, starts_block_after};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::Arc;
+use wasmer::wasmparser::{BinaryReaderError, ImportSectionEntryType, Parser, Payload};
+use wasmer::Memory;
+
+/// The basic blocks of a module that get a coverage counter, found the same way by the
+/// transformer and the host
+#[derive(Debug, Clone, Default)]
+pub struct CoverageMap {
+    /// module offset of the first instruction of each block, in counter order
+    blocks: Vec<usize>,
+    /// counter of the entry block of each of the module's own functions
+    functions: Vec<u32>,
+    num_imported_functions: u32,
+}
+
+/// How often each block of a [`CoverageMap`] ran
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    map: Arc<CoverageMap>,
+    counts: Vec<u64>,
+}
+
+#[derive(Default)]
+struct FileRecord {
+    /// the most often run block starting on each line
+    lines: BTreeMap<u32, u64>,
+    /// first line, name and call count of each function
+    functions: Vec<(u32, String, u64)>,
+}
+
+impl CoverageMap {
+    pub fn parse(wasm: &[u8]) -> Result<Self, BinaryReaderError> {
+        let mut map = Self::default();
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::ImportSection(imports) => {
+                    for import in imports {
+                        if let ImportSectionEntryType::Function(_) = import?.ty {
+                            map.num_imported_functions += 1;
+                        }
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    map.functions.push(map.blocks.len() as u32);
+                    let mut operators = body.get_operators_reader()?;
+                    let mut depth = 0;
+                    let mut starts_block = true;
+                    while !operators.eof() {
+                        let (operator, offset) = operators.read_with_offset()?;
+                        if starts_block {
+                            map.blocks.push(offset);
+                        }
+                        starts_block = starts_block_after(&operator, depth);
+                        depth = depth_after(&operator, depth);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(map)
+    }
+
+    /// Number of counters
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Counter of the entry block of the module's own function `local`
+    pub(crate) fn first_block(&self, local: u32) -> u32 {
+        self.functions[local as usize]
+    }
+}
+
+impl Coverage {
+    /// Nothing ran yet
+    pub fn new(map: Arc<CoverageMap>) -> Self {
+        let counts = vec![0; map.len()];
+        Self { map, counts }
+    }
+
+    /// Reads the counters at `base` in the guest's memory
+    pub(crate) fn read(map: Arc<CoverageMap>, memory: &Memory, base: u32) -> Self {
+        let view = memory.view::<u32>();
+        let base = base as usize / 4;
+        let counts = view[base..base + map.len()]
+            .iter()
+            .map(|count| count.get() as u64)
+            .collect();
+        Self { map, counts }
+    }
+
+    /// Adds the counts of another run of the same module, e.g. of every test
+    pub fn merge(&mut self, other: &Coverage) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    pub fn map(&self) -> &Arc<CoverageMap> {
+        &self.map
+    }
+
+    /// How often each block ran, in the order of the map
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Writes an lcov tracefile, the blocks are mapped to source lines through the module's DWARF
+    /// info in `symbols`. Blocks without a location are left out.
+    pub fn write_lcov(&self, mut out: impl Write, symbols: &Symbols) -> io::Result<()> {
+        let mut files = BTreeMap::<&str, FileRecord>::new();
+        for (&offset, &count) in self.map.blocks.iter().zip(&self.counts) {
+            if let Some(Location { file, line, .. }) = symbols.location(offset) {
+                if line > 0 {
+                    let lines = &mut files.entry(file).or_default().lines;
+                    let hits = lines.entry(line).or_default();
+                    *hits = (*hits).max(count);
+                }
+            }
+        }
+        for (local, &block) in self.map.functions.iter().enumerate() {
+            let index = self.map.num_imported_functions + local as u32;
+            let location = symbols.location(self.map.blocks[block as usize]);
+            let Location { file, line, .. } = match location {
+                Some(location) if location.line > 0 => location,
+                _ => continue,
+            };
+            let name = match symbols.function_name(index) {
+                Some(name) => format!("{:#}", rustc_demangle::demangle(name)),
+                None => format!("<wasm function {}>", index),
+            };
+            let calls = self.counts[block as usize];
+            let functions = &mut files.entry(file).or_default().functions;
+            functions.push((line, name, calls));
+        }
+
+        for (file, record) in files {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", file)?;
+            for (line, name, _) in &record.functions {
+                writeln!(out, "FN:{},{}", line, name)?;
+            }
+            for (_, name, calls) in &record.functions {
+                writeln!(out, "FNDA:{},{}", calls, name)?;
+            }
+            let hit = record.functions.iter().filter(|f| f.2 > 0).count();
+            writeln!(out, "FNF:{}", record.functions.len())?;
+            writeln!(out, "FNH:{}", hit)?;
+            for (line, hits) in &record.lines {
+                writeln!(out, "DA:{},{}", line, hits)?;
+            }
+            let hit = record.lines.values().filter(|&&hits| hits > 0).count();
+            writeln!(out, "LF:{}", record.lines.len())?;
+            writeln!(out, "LH:{}", hit)?;
+            writeln!(out, "end_of_record")?;
+        }
+        out.flush()
+    }
+}
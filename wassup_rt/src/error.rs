@@ -0,0 +1,58 @@
+use std::fmt::{self, Display};
+use wasmer::{CompileError, ExportError, InstantiationError};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Compile(CompileError),
+    /// Boxed, it is large enough to bloat every `Result` carrying an `Error`
+    Instantiation(Box<InstantiationError>),
+    /// The guest lacks an export the runtime relies on, like `poll_runtime`
+    Export(ExportError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "failed to read module: {}", err),
+            Error::Compile(err) => write!(f, "failed to compile module: {}", err),
+            Error::Instantiation(err) => write!(f, "failed to instantiate module: {}", err),
+            Error::Export(err) => write!(f, "missing guest export: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Compile(err) => Some(err),
+            Error::Instantiation(err) => Some(&**err),
+            Error::Export(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<CompileError> for Error {
+    fn from(err: CompileError) -> Self {
+        Error::Compile(err)
+    }
+}
+
+impl From<InstantiationError> for Error {
+    fn from(err: InstantiationError) -> Self {
+        Error::Instantiation(Box::new(err))
+    }
+}
+
+impl From<ExportError> for Error {
+    fn from(err: ExportError) -> Self {
+        Error::Export(err)
+    }
+}
@@ -0,0 +1,71 @@
+use crate::wasi_api::WasiEnv;
+use std::collections::HashMap;
+use wasmer::internals::WithEnv;
+use wasmer::{Exports, Function, HostFunction, ImportObject, Store, WasmTypeList};
+
+type MakeFunction = Box<dyn Fn(&Store, &WasiEnv) -> Function + Send + Sync>;
+
+/// Host functions registered by the embedder, imported by guests with `#[wassup_std::import]`.
+///
+/// ```no_run
+/// use wassup::wasmer::{Array, WasmPtr};
+/// use wassup::{HostImports, WasiEnv};
+///
+/// fn greet(env: &WasiEnv, name: WasmPtr<u8, Array>, len: u32) -> u32 {
+///     let name = env.read_str(name, len).unwrap_or_default();
+///     println!("hello {}", name);
+///     name.len() as u32
+/// }
+///
+/// let imports = HostImports::new().func("greeter", "greet", greet);
+/// ```
+///
+/// Functions get the instance's [`WasiEnv`] as first argument. They can't capture state,
+/// per-instance data goes through [`InstanceBuilder::data`](crate::InstanceBuilder::data).
+/// Returning `Err` traps the guest.
+#[derive(Default)]
+pub struct HostImports {
+    funcs: Vec<(String, String, MakeFunction)>,
+}
+
+impl HostImports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `func` as `module::name`
+    pub fn func<F, Args, Rets>(
+        mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        func: F,
+    ) -> Self
+    where
+        F: HostFunction<Args, Rets, WithEnv, WasiEnv> + Copy + Send + Sync + 'static,
+        Args: WasmTypeList,
+        Rets: WasmTypeList,
+    {
+        let make = move |store: &Store, env: &WasiEnv| {
+            Function::new_native_with_env(store, env.clone(), func)
+        };
+        self.funcs.push((module.into(), name.into(), Box::new(make)));
+        self
+    }
+
+    /// Creates the functions for one instance
+    pub(crate) fn to_import_object(&self, store: &Store, env: &WasiEnv) -> ImportObject {
+        let mut modules = HashMap::<&str, Exports>::new();
+        for (module, name, make) in &self.funcs {
+            modules
+                .entry(module)
+                .or_default()
+                .insert(name.as_str(), make(store, env));
+        }
+
+        let mut import_object = ImportObject::new();
+        for (module, exports) in modules {
+            import_object.register(module, exports);
+        }
+        import_object
+    }
+}
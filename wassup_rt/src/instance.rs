@@ -0,0 +1,598 @@
+use crate::backtrace::{Backtrace, Symbols};
+use crate::coverage::{Coverage, CoverageMap};
+use crate::error::Error;
+use crate::host_imports::HostImports;
+use crate::memory_limit::{MemoryLimit, MemoryLimitExceeded};
+use crate::metrics::Metrics;
+use crate::profile::{Profile, Sampler};
+use crate::trace::CallTrace;
+use crate::transformer::{
+    COVERAGE_GLOBAL, COVERAGE_LEN_GLOBAL, FUEL_GLOBAL, INSTRUMENTATION_ALLOC, INSTRUMENTED_MEMORY,
+    INTERRUPT_GLOBAL, SHADOW_DEPTH_GLOBAL, SHADOW_STACK_FRAMES, SHADOW_STACK_GLOBAL,
+};
+use crate::wasi_api::{self, Delivery, GuestPanic, GuestTask, Ipc, Panicked, State, WasiEnv};
+use crate::watchdog::{DeadlineExceeded, Watchdog};
+use std::any::Any;
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmer::{
+    imports, Export, Function, Global, ImportObject, Instance, Module, NativeFunc, Resolver,
+    RuntimeError, Value,
+};
+
+/// Resolves imports from the first resolver that provides them
+pub struct ComboResolver<'a>(pub Vec<&'a (dyn Resolver + Sync + Send)>);
+
+impl<'a> Resolver for ComboResolver<'a> {
+    fn resolve(&self, index: u32, module: &str, field: &str) -> Option<Export> {
+        for r in &self.0 {
+            if let x @ Some(_) = r.resolve(index, module, field) {
+                return x;
+            }
+        }
+        None
+    }
+}
+
+/// Raised by the `shutdown_rt` import to unwind out of the guest once its runtime is done,
+/// holds the exit code reported by the guest.
+#[derive(Debug)]
+pub struct Shutdown(pub u32);
+
+impl Display for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "guest runtime shut down with exit code {}", self.0)
+    }
+}
+
+impl std::error::Error for Shutdown {}
+
+/// What the guest runtime wants after a call returned
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Poll again after the duration at the latest, a huge duration means the guest only waits
+    /// for a wakeup
+    Pending(Duration),
+    /// The guest runtime shut down with the exit code
+    Exited(u32),
+}
+
+pub struct InstanceBuilder<'a> {
+    module: &'a Module,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    data: Option<Arc<dyn Any + Send + Sync>>,
+    host_imports: Vec<&'a HostImports>,
+    imports: Vec<ImportObject>,
+    max_channels: usize,
+    memory_limit: Option<u64>,
+    poll_deadline: Option<Duration>,
+    profile: Option<Duration>,
+    symbols: Option<Arc<Symbols>>,
+}
+
+/// A guest instance with its own wasi state
+pub struct GuestInstance {
+    instance: Instance,
+    env: WasiEnv,
+    poll: NativeFunc<(), u64>,
+    ipc_notify: Option<NativeFunc<(u32, u32), u16>>,
+    ipc_writable: Option<NativeFunc<u32, ()>>,
+    ipc_closed: Option<NativeFunc<u32, ()>>,
+    watchdog: Option<Watchdog>,
+    /// absent if the module wasn't instrumented
+    fuel: Option<Global>,
+    /// a call had to be interrupted
+    misbehaving: AtomicBool,
+    sampler: Option<Sampler>,
+    symbols: Option<Arc<Symbols>>,
+}
+
+impl<'a> InstanceBuilder<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        Self {
+            module,
+            args: vec![],
+            envs: vec![],
+            data: None,
+            host_imports: vec![],
+            imports: vec![],
+            max_channels: 128,
+            memory_limit: None,
+            poll_deadline: None,
+            profile: None,
+            symbols: None,
+        }
+    }
+
+    /// Sets the arguments returned by wasi's `args_get`, including the program name
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds an environment variable returned by wasi's `environ_get`
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the data host functions get through [`WasiEnv::data`]
+    pub fn data<T: Any + Send + Sync>(mut self, data: T) -> Self {
+        self.data = Some(Arc::new(data));
+        self
+    }
+
+    /// Adds typed host functions, they take precedence over the built-in imports
+    pub fn host_imports(mut self, imports: &'a HostImports) -> Self {
+        self.host_imports.push(imports);
+        self
+    }
+
+    /// Limits how many channels the guest may have open at once, 128 by default
+    pub fn max_channels(mut self, max_channels: usize) -> Self {
+        self.max_channels = max_channels;
+        self
+    }
+
+    /// Caps the guest's linear memory at `bytes`, rounded down to whole wasm pages. Growing past
+    /// it fails inside the guest, a trap caused by that is reported as [`MemoryLimitExceeded`].
+    pub fn memory_limit(mut self, bytes: u64) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Gives each call into the guest runtime `budget` of wall-clock time. After that
+    /// `yield_requested` asks it to return, if it is still running after another `budget` it traps
+    /// with [`DeadlineExceeded`].
+    pub fn poll_deadline(mut self, budget: Duration) -> Self {
+        self.poll_deadline = Some(budget);
+        self
+    }
+
+    /// Samples the running guest functions every `interval` into a [`Profile`], the module has to
+    /// be compiled with [`ModuleTransformer::shadow_stack`](crate::ModuleTransformer::shadow_stack)
+    pub fn profile(mut self, interval: Duration) -> Self {
+        self.profile = Some(interval);
+        self
+    }
+
+    /// Source locations for [`GuestInstance::backtrace`], from [`Symbols::parse`]
+    pub fn symbols(mut self, symbols: Arc<Symbols>) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Adds host imports, they take precedence over the built-in ones
+    pub fn imports(mut self, imports: ImportObject) -> Self {
+        self.imports.push(imports);
+        self
+    }
+
+    pub fn build(self) -> Result<GuestInstance, Error> {
+        let store = self.module.store();
+
+        let mut state = State::new();
+        state.args = self.args;
+        state.envs = self.envs;
+        state.data = self.data;
+        state.max_ipcs = self.max_channels;
+        state.memory_limit = self
+            .memory_limit
+            .map(|bytes| Arc::new(MemoryLimit::new(bytes, state.instance_id)));
+        let mut env = WasiEnv {
+            memory: Default::default(),
+            state: Arc::new(state),
+        };
+
+        let env_imports = imports! {
+            "env" => {
+                "yield_rt" => Global::new(store, Value::I32(0)),
+                "yield_requested" => Function::new_native_with_env(store, env.clone(), |env: &WasiEnv| {
+                    env.state.yield_requested.load(Ordering::Acquire) as u32
+                }),
+                "wake" => Function::new_native_with_env(store, env.clone(), |env: &WasiEnv| {
+                    env.state.metrics.wake()
+                }),
+                "log_n" => Function::new_native(store, |_: u64| ()),
+                "shutdown_rt" => Function::new_native(store, |exit_code: u32| -> Result<(), Shutdown> {
+                    Err(Shutdown(exit_code))
+                }),
+            }
+        };
+        let wasi_imports = wasi_api::generate_imports(store, env.clone());
+        let host_imports = self
+            .host_imports
+            .iter()
+            .map(|imports| imports.to_import_object(store, &env))
+            .collect::<Vec<_>>();
+
+        let mut resolvers = host_imports
+            .iter()
+            .chain(&self.imports)
+            .map(|imports| imports as &(dyn Resolver + Sync + Send))
+            .collect::<Vec<_>>();
+        resolvers.push(&env_imports);
+        resolvers.push(&wasi_imports);
+
+        let instance = MemoryLimit::scope(env.state.memory_limit.clone(), || {
+            Instance::new(self.module, &ComboResolver(resolvers)).map_err(Error::from)
+        })?;
+        // the imports got clones of `env`, initialized on their own
+        env.memory.initialize(instance.exports.get_memory("memory")?.clone());
+        allocate_instrumentation(&instance, SHADOW_STACK_GLOBAL, SHADOW_STACK_FRAMES * 4);
+        if let Ok(len) = instance.exports.get_global(COVERAGE_LEN_GLOBAL) {
+            let counters = len.get().i32().unwrap_or_default() as u32;
+            allocate_instrumentation(&instance, COVERAGE_GLOBAL, counters * 4);
+        }
+        let poll = instance
+            .exports
+            .get_native_function::<(), u64>("poll_runtime")?;
+        let ipc_notify = instance
+            .exports
+            .get_native_function::<(u32, u32), u16>("ipc_notify")
+            .ok();
+        let ipc_writable = instance
+            .exports
+            .get_native_function::<u32, ()>("ipc_writable")
+            .ok();
+        let ipc_closed = instance
+            .exports
+            .get_native_function::<u32, ()>("ipc_closed")
+            .ok();
+        let watchdog = self.poll_deadline.map(|budget| {
+            let interrupt = instance.exports.get_global(INTERRUPT_GLOBAL).ok().cloned();
+            Watchdog::spawn(env.state.clone(), budget, interrupt)
+        });
+        let fuel = instance.exports.get_global(FUEL_GLOBAL).ok().cloned();
+        let sampler = match self.profile {
+            Some(interval) => Some(Self::sampler(&instance, env.state.instance_id, interval)?),
+            None => None,
+        };
+
+        Ok(GuestInstance {
+            instance,
+            env,
+            poll,
+            ipc_notify,
+            ipc_writable,
+            ipc_closed,
+            watchdog,
+            fuel,
+            misbehaving: AtomicBool::new(false),
+            sampler,
+            symbols: self.symbols,
+        })
+    }
+
+    fn sampler(instance: &Instance, instance_id: u64, interval: Duration) -> Result<Sampler, Error> {
+        let memory = instance.exports.get_memory(INSTRUMENTED_MEMORY)?.clone();
+        let base = instance.exports.get_global(SHADOW_STACK_GLOBAL)?.get();
+        let depth = instance.exports.get_global(SHADOW_DEPTH_GLOBAL)?.clone();
+        let base = base.i32().unwrap_or_default() as u32;
+        Ok(Sampler::spawn(instance_id, interval, memory, base, depth))
+    }
+}
+
+/// Points the instrumentation's `global` at `bytes` from the guest's allocator, the guest keeps
+/// running without it if that fails
+fn allocate_instrumentation(instance: &Instance, global: &str, bytes: u32) {
+    let global = match instance.exports.get_global(global) {
+        Ok(global) => global,
+        // the module wasn't compiled with it
+        Err(_) => return,
+    };
+    let alloc = match instance
+        .exports
+        .get_native_function::<u32, u32>(INSTRUMENTATION_ALLOC)
+    {
+        Ok(alloc) => alloc,
+        Err(err) => {
+            log::warn!("can't allocate the guest's instrumentation: {}", err);
+            return;
+        }
+    };
+    match alloc.call(bytes + 3) {
+        Ok(ptr) if ptr != 0 => {
+            let _ = global.set(Value::I32(((ptr + 3) & !3) as i32));
+        }
+        Ok(_) => log::warn!("the guest couldn't allocate {} bytes of instrumentation", bytes),
+        Err(err) => log::warn!("failed to allocate the guest's instrumentation: {}", err),
+    }
+}
+
+impl GuestInstance {
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    pub fn env(&self) -> &WasiEnv {
+        &self.env
+    }
+
+    /// Calls an exported `fn()` entry point like `_start` or a test
+    pub fn call(&self, entry: &str) -> Result<Status, RuntimeError> {
+        let entry = self
+            .instance
+            .exports
+            .get_native_function::<(), ()>(entry)
+            .map_err(|err| RuntimeError::new(err.to_string()))?;
+        self.watched(|| entry.call().map(|_| 0))
+    }
+
+    pub fn start(&self) -> Result<Status, RuntimeError> {
+        self.call("_start")
+    }
+
+    /// Whether a call into the guest had to be interrupted for running past its
+    /// [deadline](InstanceBuilder::poll_deadline), such an instance refuses further calls
+    pub fn is_misbehaving(&self) -> bool {
+        self.misbehaving.load(Ordering::Acquire)
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.env.state.metrics
+    }
+
+    /// What the guest's panic hook reported, if it panicked
+    pub fn panic(&self) -> Option<GuestPanic> {
+        self.env.state.panic.lock().unwrap().clone()
+    }
+
+    /// Asks the guest runtime for a snapshot of its tasks. Works between polls and after the
+    /// guest trapped, e.g. on its deadline, where the task it was stuck in is still running.
+    pub fn tasks(&self) -> Result<Vec<GuestTask>, RuntimeError> {
+        let dump = self
+            .instance
+            .exports
+            .get_native_function::<(), ()>("__wassup_dump_tasks")
+            .map_err(|err| RuntimeError::new(err.to_string()))?;
+        dump.call()?;
+        Ok(std::mem::take(&mut *self.env.state.tasks.lock().unwrap()))
+    }
+
+    /// The calls recorded so far, if the module was compiled with
+    /// [`ModuleTransformer::trace_calls`](crate::ModuleTransformer::trace_calls)
+    pub fn call_trace(&self) -> CallTrace {
+        self.env.state.trace.lock().unwrap().clone()
+    }
+
+    /// The stacks sampled so far, if [`InstanceBuilder::profile`] was given
+    pub fn profile(&self) -> Option<Profile> {
+        self.sampler.as_ref().map(Sampler::profile)
+    }
+
+    /// How often each block ran so far, the module has to be compiled with
+    /// [`ModuleTransformer::coverage`](crate::ModuleTransformer::coverage) and `map` found in it
+    pub fn coverage(&self, map: &Arc<CoverageMap>) -> Result<Coverage, Error> {
+        let exports = &self.instance.exports;
+        let memory = exports.get_memory(INSTRUMENTED_MEMORY)?;
+        let base = exports.get_global(COVERAGE_GLOBAL)?.get();
+        match base.i32().unwrap_or_default() as u32 {
+            // the guest couldn't allocate the counters
+            0 => Ok(Coverage::new(map.clone())),
+            base => Ok(Coverage::read(map.clone(), memory, base)),
+        }
+    }
+
+    /// Formats a trap of this instance with the guest's panic message and a symbolized backtrace
+    pub fn backtrace(&self, error: &RuntimeError) -> Backtrace<'_> {
+        Backtrace::new(error, self.symbols.as_deref())
+    }
+
+    /// Takes the next channel the guest opened
+    pub fn accept_ipc(&self) -> Option<Ipc> {
+        self.env.state.new_ipcs.pop()
+    }
+
+    pub fn ipc(&self, id: u32) -> Option<Ipc> {
+        self.env.state.ipcs.get(&id).map(|ipc| ipc.clone())
+    }
+
+    /// Delivers the messages queued with [`Ipc::try_send`], polls the guest runtime once and picks
+    /// up what the guest left in shared rings
+    pub fn poll(&self) -> Result<Status, RuntimeError> {
+        self.deliver_ipc()?;
+        let started = Instant::now();
+        let status = self.watched(|| self.poll.call());
+        self.metrics().poll(started.elapsed());
+        for ipc in self.env.state.ipcs.iter() {
+            ipc.drain_ring(self.env.memory());
+        }
+        status
+    }
+
+    fn deliver_ipc(&self) -> Result<(), RuntimeError> {
+        let notify = match &self.ipc_notify {
+            Some(notify) => notify,
+            None => return Ok(()),
+        };
+
+        let ipcs = self
+            .env
+            .state
+            .ipcs
+            .iter()
+            .map(|ipc| ipc.clone())
+            .collect::<Vec<_>>();
+        for ipc in ipcs {
+            let mut wake_ring = false;
+            while let Some(delivery) = ipc.next_delivery(self.env.memory()) {
+                let errno = match delivery {
+                    Delivery::Notify(msg) => notify.call(ipc.id(), msg.len() as u32)?,
+                    Delivery::RingWasEmpty => {
+                        wake_ring = true;
+                        continue;
+                    }
+                    Delivery::Ring => continue,
+                };
+                match errno {
+                    wasi::ERRNO_SUCCESS => {}
+                    // the guest holds as many messages as the channel's capacity already
+                    wasi::ERRNO_AGAIN => {
+                        ipc.redeliver();
+                        break;
+                    }
+                    errno => log::warn!(
+                        "[instance {}] dropped message on channel {}: {}",
+                        self.env.state.instance_id,
+                        ipc.id(),
+                        wasi::errno_name(errno),
+                    ),
+                }
+            }
+            if wake_ring {
+                notify.call(ipc.id(), 0)?;
+            }
+            if let Some(writable) = &self.ipc_writable {
+                if ipc.take_writable() {
+                    writable.call(ipc.id())?;
+                }
+            }
+            if let Some(closed) = &self.ipc_closed {
+                if ipc.take_close() {
+                    closed.call(ipc.id())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `_start` and keeps polling until the guest shuts down, returns its exit code
+    pub fn run(&self) -> Result<u32, RuntimeError> {
+        self.run_entry("_start")
+    }
+
+    /// Calls `entry` and keeps polling until the guest shuts down, returns its exit code
+    pub fn run_entry(&self, entry: &str) -> Result<u32, RuntimeError> {
+        let mut status = self.call(entry)?;
+        loop {
+            match status {
+                Status::Pending(sleep_time) => std::thread::sleep(sleep_time),
+                Status::Exited(exit_code) => return Ok(exit_code),
+            }
+            status = self.poll()?;
+        }
+    }
+
+    /// Runs a call into the guest runtime under the watchdog, if there is one
+    fn watched(
+        &self,
+        f: impl FnOnce() -> Result<u64, RuntimeError>,
+    ) -> Result<Status, RuntimeError> {
+        if let Some(limit) = &self.env.state.memory_limit {
+            limit.reset();
+        }
+        let watchdog = match &self.watchdog {
+            Some(watchdog) => watchdog,
+            None => return self.status(f()),
+        };
+        let deadline_exceeded = || {
+            RuntimeError::user(Box::new(DeadlineExceeded {
+                budget: watchdog.budget(),
+            }))
+        };
+        if self.is_misbehaving() {
+            return Err(deadline_exceeded());
+        }
+
+        match watchdog.watch(f) {
+            (Err(_), true) => {
+                self.metrics().trap();
+                self.misbehaving.store(true, Ordering::Release);
+                Err(deadline_exceeded())
+            }
+            (result, _) => self.status(result),
+        }
+    }
+
+    fn status(&self, result: Result<u64, RuntimeError>) -> Result<Status, RuntimeError> {
+        self.env.state.trace.lock().unwrap().unwind();
+        if let Some(sampler) = &self.sampler {
+            sampler.reset();
+        }
+        self.metrics().set_memory_pages(self.env.memory().size().0);
+        if let Some(fuel) = &self.fuel {
+            self.metrics().set_fuel(fuel.get().i64().unwrap_or_default() as u64);
+        }
+        match result {
+            Ok(sleep_time) => Ok(Status::Pending(Duration::from_micros(sleep_time))),
+            Err(err) => err
+                .downcast::<Shutdown>()
+                .map(|Shutdown(exit_code)| Status::Exited(exit_code))
+                .map_err(|err| {
+                    self.metrics().trap();
+                    match &self.env.state.memory_limit {
+                        // the guest aborts or traps on the failed allocation
+                        Some(limit) if limit.exceeded() => {
+                            RuntimeError::user(Box::new(MemoryLimitExceeded {
+                                limit: limit.bytes(),
+                            }))
+                        }
+                        // errors raised by host functions aren't traps and have nothing to do with it
+                        _ => match self.panic() {
+                            Some(panic) if err.clone().to_trap().is_some() => {
+                                RuntimeError::user(Box::new(Panicked { panic, trap: err }))
+                            }
+                            _ => err,
+                        },
+                    }
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Backend, ModuleTransformer};
+
+    const BUDGET: Duration = Duration::from_millis(20);
+
+    /// A guest whose `poll_runtime` spins until `until` returns non-zero
+    fn spinning_guest(until: &str) -> GuestInstance {
+        let store = Backend::default().make_store(Arc::new(ModuleTransformer::default()));
+        let wat = format!(
+            r#"(module
+                (import "env" "yield_requested" (func $yield_requested (result i32)))
+                (func $never (result i32) i32.const 0)
+                (memory (export "memory") 1)
+                (func (export "poll_runtime") (result i64)
+                    (loop $spin
+                        call ${}
+                        i32.eqz
+                        br_if $spin)
+                    i64.const 0))"#,
+            until
+        );
+        let module = Module::new(&store, wat).unwrap();
+        InstanceBuilder::new(&module)
+            .poll_deadline(BUDGET)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn guest_yields_when_asked() {
+        let instance = spinning_guest("yield_requested");
+        let started = Instant::now();
+        assert!(matches!(instance.poll(), Ok(Status::Pending(_))));
+        assert!(started.elapsed() >= BUDGET);
+        assert!(!instance.is_misbehaving());
+        assert!(!instance.env.state.yield_requested.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn guest_ignoring_yield_is_interrupted() {
+        let instance = spinning_guest("never");
+        let err = instance.poll().unwrap_err();
+        assert!(err.downcast::<DeadlineExceeded>().is_ok());
+        assert!(instance.is_misbehaving());
+    }
+}
@@ -0,0 +1,51 @@
+//! Host runtime for wassup guests.
+//!
+//! ```no_run
+//! use wassup::{InstanceBuilder, Runtime};
+//!
+//! let runtime = Runtime::default();
+//! let module = runtime.load_file("guest.wasm").unwrap();
+//! let instance = InstanceBuilder::new(&module)
+//!     .args(["guest", "--verbose"])
+//!     .env("GREETING", "hello")
+//!     .build()
+//!     .unwrap();
+//! let exit_code = instance.run().unwrap();
+//! ```
+
+mod backtrace;
+mod cache;
+mod compiler;
+mod coverage;
+mod error;
+mod host_imports;
+mod instance;
+mod memory_limit;
+pub mod metrics;
+mod profile;
+#[cfg(any(feature = "serde_json", feature = "bincode", feature = "postcard"))]
+pub mod rpc;
+mod runtime;
+pub mod supervisor;
+pub mod test_runner;
+mod trace;
+mod transformer;
+mod wasi_api;
+mod watchdog;
+
+pub use backtrace::{Backtrace, Location, Symbols};
+pub use cache::ModuleCache;
+pub use compiler::Backend;
+pub use coverage::{Coverage, CoverageMap};
+pub use error::Error;
+pub use host_imports::HostImports;
+pub use instance::{ComboResolver, GuestInstance, InstanceBuilder, Shutdown, Status};
+pub use memory_limit::{MemoryLimit, MemoryLimitExceeded};
+pub use profile::Profile;
+pub use runtime::Runtime;
+pub use trace::CallTrace;
+pub use transformer::ModuleTransformer;
+pub use wasi_api::{GuestPanic, GuestTask, Ipc, Panicked, State, TaskState, TrySendError, WasiEnv};
+pub use watchdog::DeadlineExceeded;
+
+pub use wasmer;
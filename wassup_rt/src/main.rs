@@ -1,93 +1,198 @@
-extern crate core;
-
-use crate::transformer::ModuleTransformer;
-use crate::wasi_api::{State, WasiEnv};
-
-
 use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use wasmer::{
-    imports, CompilerConfig, Export, Function, Global, Instance, Module,
-    Resolver, Store, Value,
+use wassup::supervisor::{Exit, Restart, RestartPolicy, Supervisor};
+use wassup::{
+    test_runner, Backend, Coverage, CoverageMap, InstanceBuilder, ModuleTransformer, Runtime,
+    Symbols,
 };
-use wasmer_compiler_llvm::{LLVMOptLevel, LLVM};
-use wasmer_engine_universal::Universal;
 
-mod transformer;
-mod wasi_api;
+const DEFAULT_MODULE: &str = "target/wasm32-wasi/release/test_bin.wasm";
 
-struct ComboResolver<'a, const N: usize>(pub [&'a (dyn Resolver + Sync + Send); N]);
+fn main() {
+    // guest logs are filtered by the host, e.g. `WASSUP_LOG=info,my_guest::net=trace`
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or("WASSUP_LOG", "info")).init();
+
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    // `--compiler <llvm|cranelift|singlepass>` may be given anywhere
+    let backend = match args.iter().position(|arg| arg == "--compiler") {
+        Some(pos) => {
+            let name = args.get(pos + 1).expect("--compiler requires a value").clone();
+            args.drain(pos..pos + 2);
+            name.parse::<Backend>().unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(2)
+            })
+        }
+        None => Backend::default(),
+    };
+    // `--restart <never|on-failure|always>` may be given anywhere
+    let restart = match args.iter().position(|arg| arg == "--restart") {
+        Some(pos) => {
+            let name = args.get(pos + 1).expect("--restart requires a value").clone();
+            args.drain(pos..pos + 2);
+            name.parse::<Restart>().unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(2)
+            })
+        }
+        None => Restart::Never,
+    };
+    // `--coverage <lcov file>` makes `test` write the blocks the tests ran
+    let coverage = match args.iter().position(|arg| arg == "--coverage") {
+        Some(pos) => {
+            let path = args.get(pos + 1).expect("--coverage requires a value").clone();
+            args.drain(pos..pos + 2);
+            Some(path)
+        }
+        None => None,
+    };
+    // `--metrics <file>` rewrites the file with the Prometheus text format every second
+    if let Some(pos) = args.iter().position(|arg| arg == "--metrics") {
+        let path = args.get(pos + 1).expect("--metrics requires a value").clone();
+        args.drain(pos..pos + 2);
+        spawn_metrics_writer(path);
+    }
 
-impl<'a, const N: usize> Resolver for ComboResolver<'a, N> {
-    fn resolve(&self, index: u32, module: &str, field: &str) -> Option<Export> {
-        for r in self.0 {
-            if let x @ Some(_) = r.resolve(index, module, field) {
-                return x;
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        Some("test") => {
+            let path = args.next().unwrap_or_else(|| DEFAULT_MODULE.to_string());
+            let filter = args.next();
+            let wasm = std::fs::read(path).unwrap();
+            let transformer = ModuleTransformer::default().coverage(coverage.is_some());
+            let module = Runtime::with_transformer(backend, transformer).load(&wasm).unwrap();
+            let symbols = Arc::new(Symbols::parse(&wasm));
+            let mut counts = coverage
+                .as_ref()
+                .map(|_| Coverage::new(Arc::new(CoverageMap::parse(&wasm).unwrap())));
+            let passed = test_runner::run_tests(
+                &module,
+                Some(symbols.clone()),
+                filter.as_deref(),
+                counts.as_mut(),
+            );
+            if let (Some(path), Some(counts)) = (coverage, counts) {
+                let out = BufWriter::new(File::create(&path).unwrap());
+                counts.write_lcov(out, &symbols).unwrap();
+                println!("coverage written to {}", path);
+            }
+            std::process::exit(if passed { 0 } else { 101 });
+        }
+        Some("trace") => {
+            // `wassup trace <module> [output]` writes folded stacks for flame graph tools
+            let path = args.next().unwrap_or_else(|| DEFAULT_MODULE.to_string());
+            let output = args.next().unwrap_or_else(|| "trace.folded".to_string());
+            let wasm = std::fs::read(&path).unwrap();
+            let transformer = ModuleTransformer::default().trace_calls(true);
+            let module = Runtime::with_transformer(backend, transformer).load(&wasm).unwrap();
+            let instance = InstanceBuilder::new(&module).args([path]).build().unwrap();
+            let result = instance.run();
+
+            let out = BufWriter::new(File::create(&output).unwrap());
+            let symbols = Symbols::parse(&wasm);
+            instance.call_trace().write_folded(out, Some(&symbols)).unwrap();
+            println!("call trace written to {}", output);
+            std::process::exit(match result {
+                Ok(exit_code) => exit_code as i32,
+                Err(err) => {
+                    eprintln!("{}", instance.backtrace(&err));
+                    101
+                }
+            });
+        }
+        Some("profile") => {
+            // `wassup profile <module> [output]` samples the running functions every millisecond
+            let path = args.next().unwrap_or_else(|| DEFAULT_MODULE.to_string());
+            let output = args.next().unwrap_or_else(|| "profile.folded".to_string());
+            let wasm = std::fs::read(&path).unwrap();
+            let transformer = ModuleTransformer::default().shadow_stack(true);
+            let module = Runtime::with_transformer(backend, transformer).load(&wasm).unwrap();
+            let instance = InstanceBuilder::new(&module)
+                .args([path])
+                .profile(Duration::from_millis(1))
+                .build()
+                .unwrap();
+            let result = instance.run();
+
+            let profile = instance.profile().unwrap();
+            let out = BufWriter::new(File::create(&output).unwrap());
+            profile.write_folded(out, Some(&Symbols::parse(&wasm))).unwrap();
+            println!("{} samples written to {}", profile.len(), output);
+            std::process::exit(match result {
+                Ok(exit_code) => exit_code as i32,
+                Err(err) => {
+                    eprintln!("{}", instance.backtrace(&err));
+                    101
+                }
+            });
+        }
+        Some("compile") => {
+            // fill the cache ahead of time, so later runs skip compilation
+            let runtime = Runtime::new(backend);
+            let paths = args.collect::<Vec<_>>();
+            let paths = if paths.is_empty() { vec![DEFAULT_MODULE.to_string()] } else { paths };
+            for path in paths {
+                let wasm = std::fs::read(&path).unwrap();
+                runtime.compile(&wasm).unwrap();
+                println!("{} -> {}", path, runtime.cache().path(&wasm).display());
             }
+            return;
         }
-        None
+        _ => {}
     }
-}
 
-fn main() {
     let mut stamper = Stamper::now();
 
-    let wasm = std::fs::read("target/wasm32-wasi/release/test_bin.wasm").unwrap();
+    let wasm = std::fs::read(DEFAULT_MODULE).unwrap();
     stamper.stamp("wasm loaded");
 
-    let mut compiler = LLVM::default();
-    compiler.opt_level(LLVMOptLevel::Aggressive);
-    compiler.push_middleware(Arc::new(ModuleTransformer::default()));
-    stamper.stamp("mk-compiler");
-
-    let store = Store::new(&Universal::new(compiler).engine());
+    let runtime = Runtime::new(backend);
     stamper.stamp("mk-store");
 
-    let module = Module::new(&store, wasm).unwrap();
-    stamper.stamp("compile module");
-
-    let env_imports = imports! {
-        "env" => {
-            "yield_rt" => Global::new(&store, Value::I32(0)),
-            "wake" => Function::new_native(&store, || println!("wakeup lmao")),
-            "log_n" => Function::new_native(&store, |_: u64| ()),
-            "shutdown_rt" => Function::new_native(&store, || {
-                println!("exit trigger");
-                std::process::exit(0)
-            }),
+    let module = runtime.load(&wasm).unwrap();
+    stamper.stamp("load module");
+
+    let symbols = Arc::new(Symbols::parse(&wasm));
+    let mut supervisor = Supervisor::new();
+    supervisor.add(DEFAULT_MODULE, RestartPolicy::new(restart), || {
+        InstanceBuilder::new(&module)
+            .args([DEFAULT_MODULE])
+            .symbols(symbols.clone())
+            .build()
+    });
+    let (_, exit) = supervisor.run().pop().unwrap();
+    println!("exit trigger");
+    std::process::exit(match exit {
+        Exit::Code(exit_code) => exit_code as i32,
+        exit => {
+            eprintln!("{}", exit);
+            101
         }
-    };
-    let wasi_imports = wasi_api::generate_imports(
-        &store,
-        WasiEnv {
-            memory: Default::default(),
-            state: Arc::new(State::new())
-        },
-    );
-
-    let imports = ComboResolver([&env_imports, &wasi_imports]);
-    stamper.stamp("mk-imports");
-
-    let instance = Instance::new(&module, &imports).unwrap();
-    stamper.stamp("mk-instance");
-
-    let start = instance
-        .exports
-        .get_native_function::<(), ()>("_start")
-        .expect("_start must be present");
-    let poll = instance
-        .exports
-        .get_native_function::<(), u64>("poll_runtime")
-        .expect("poll_runtime must be present");
-
-    start.call().unwrap();
-
-    loop {
-        let sleep_time = poll.call().unwrap();
+    });
+}
 
-        std::thread::sleep(Duration::from_micros(sleep_time))
-    }
+/// Writes to a temporary file first, so scrapers never see a partial one
+fn spawn_metrics_writer(path: String) {
+    let tmp = format!("{}.tmp", path);
+    std::thread::Builder::new()
+        .name("wassup-metrics".to_string())
+        .spawn(move || loop {
+            let written = File::create(&tmp)
+                .and_then(|file| {
+                    let mut out = BufWriter::new(file);
+                    wassup::metrics::write_metrics(&mut out)?;
+                    out.flush()
+                })
+                .and_then(|_| std::fs::rename(&tmp, &path));
+            if let Err(err) = written {
+                eprintln!("failed to write metrics to {}: {}", path, err);
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        })
+        .expect("failed to spawn metrics thread");
 }
 
 struct Stamper(Instant, Instant);
@@ -0,0 +1,217 @@
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wasmer::vm::{self, MemoryStyle, TableStyle, VMMemoryDefinition, VMTableDefinition};
+use wasmer::{BaseTunables, MemoryError, MemoryType, Pages, TableType, Tunables, WASM_PAGE_SIZE};
+
+thread_local! {
+    /// Limit for the memories of the instance being created on this thread
+    static CURRENT_LIMIT: RefCell<Option<Arc<MemoryLimit>>> = const { RefCell::new(None) };
+}
+
+/// The most linear memory one instance may use
+#[derive(Debug)]
+pub struct MemoryLimit {
+    bytes: u64,
+    instance_id: u64,
+    /// set when a `memory.grow` was refused, cleared whenever the host calls into the guest
+    exceeded: AtomicBool,
+}
+
+/// The guest tried to grow its memory past its limit. It sees a failed `memory.grow`, which
+/// usually ends in an allocation failure and a trap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryLimitExceeded {
+    pub limit: u64,
+}
+
+impl MemoryLimit {
+    pub(crate) fn new(bytes: u64, instance_id: u64) -> Self {
+        Self {
+            bytes,
+            instance_id,
+            exceeded: AtomicBool::new(false),
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Whether the guest hit the limit during the last call into it
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Acquire)
+    }
+
+    /// Forgets a limit the guest hit and recovered from in an earlier call
+    pub(crate) fn reset(&self) {
+        self.exceeded.store(false, Ordering::Release);
+    }
+
+    fn pages(&self) -> Pages {
+        Pages((self.bytes / WASM_PAGE_SIZE as u64).min(u32::MAX as u64) as u32)
+    }
+
+    /// Applies the limit to the memories created by `f`
+    pub(crate) fn scope<R>(limit: Option<Arc<MemoryLimit>>, f: impl FnOnce() -> R) -> R {
+        let previous = CURRENT_LIMIT.with(|current| current.replace(limit));
+        let result = f();
+        CURRENT_LIMIT.with(|current| *current.borrow_mut() = previous);
+        result
+    }
+}
+
+/// Wraps wasmer's default tunables, caps the memories of instances created inside
+/// [`MemoryLimit::scope`].
+#[derive(MemoryUsage)]
+pub(crate) struct LimitingTunables {
+    base: BaseTunables,
+}
+
+impl LimitingTunables {
+    pub fn new(base: BaseTunables) -> Self {
+        Self { base }
+    }
+
+    fn limit(ty: &MemoryType) -> Result<(MemoryType, Option<Arc<MemoryLimit>>), MemoryError> {
+        let limit = match CURRENT_LIMIT.with(|current| current.borrow().clone()) {
+            Some(limit) => limit,
+            None => return Ok((*ty, None)),
+        };
+
+        let max_pages = limit.pages();
+        if ty.minimum > max_pages {
+            return Err(MemoryError::MinimumMemoryTooLarge {
+                min_requested: ty.minimum,
+                max_allowed: max_pages,
+            });
+        }
+        let mut ty = *ty;
+        ty.maximum = Some(ty.maximum.map_or(max_pages, |max| max.min(max_pages)));
+
+        Ok((ty, Some(limit)))
+    }
+}
+
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(memory)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<Arc<dyn vm::Memory>, MemoryError> {
+        let (ty, limit) = Self::limit(ty)?;
+        let memory = self.base.create_host_memory(&ty, style)?;
+        Ok(LimitedMemory::wrap(memory, limit))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<Arc<dyn vm::Memory>, MemoryError> {
+        let (ty, limit) = Self::limit(ty)?;
+        let memory = self
+            .base
+            .create_vm_memory(&ty, style, vm_definition_location)?;
+        Ok(LimitedMemory::wrap(memory, limit))
+    }
+
+    fn create_host_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+    ) -> Result<Arc<dyn vm::Table>, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<Arc<dyn vm::Table>, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+}
+
+/// Reports growing past the limit, wasmer already refuses it through the lowered maximum
+#[derive(Debug)]
+struct LimitedMemory {
+    inner: Arc<dyn vm::Memory>,
+    limit: Arc<MemoryLimit>,
+}
+
+impl LimitedMemory {
+    fn wrap(memory: Arc<dyn vm::Memory>, limit: Option<Arc<MemoryLimit>>) -> Arc<dyn vm::Memory> {
+        match limit {
+            Some(limit) => Arc::new(Self {
+                inner: memory,
+                limit,
+            }),
+            None => memory,
+        }
+    }
+}
+
+impl vm::Memory for LimitedMemory {
+    fn ty(&self) -> MemoryType {
+        self.inner.ty()
+    }
+
+    fn style(&self) -> &MemoryStyle {
+        self.inner.style()
+    }
+
+    fn size(&self) -> Pages {
+        self.inner.size()
+    }
+
+    fn grow(&self, delta: Pages) -> Result<Pages, MemoryError> {
+        let current = self.inner.size();
+        if current.0 as u64 + delta.0 as u64 > self.limit.pages().0 as u64 {
+            if !self.limit.exceeded.swap(true, Ordering::AcqRel) {
+                log::warn!(
+                    "[instance {}] guest exceeded its memory limit of {} bytes",
+                    self.limit.instance_id,
+                    self.limit.bytes,
+                );
+            }
+            return Err(MemoryError::CouldNotGrow {
+                current,
+                attempted_delta: delta,
+            });
+        }
+        self.inner.grow(delta)
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.inner.vmmemory()
+    }
+}
+
+impl MemoryUsage for LimitedMemory {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        std::mem::size_of_val(self) + self.inner.size_of_val(tracker)
+    }
+}
+
+impl Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "guest exceeded its memory limit of {} bytes", self.limit)
+    }
+}
+
+impl Error for MemoryLimitExceeded {}
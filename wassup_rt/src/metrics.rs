@@ -0,0 +1,238 @@
+//! Counters kept for every guest instance, exported in the Prometheus text format.
+//!
+//! ```no_run
+//! let mut out = Vec::new();
+//! wassup::metrics::write_metrics(&mut out).unwrap();
+//! ```
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// Instances whose metrics are exported, dropped ones are pruned on the next registration or
+/// export
+static INSTANCES: Mutex<Vec<(u64, Weak<Metrics>)>> = Mutex::new(Vec::new());
+
+/// The counters of one instance. The guest runtime reports its own numbers after every poll.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    polls: AtomicU64,
+    poll_nanos: AtomicU64,
+    wakes: AtomicU64,
+    messages_to_guest: AtomicU64,
+    bytes_to_guest: AtomicU64,
+    messages_from_guest: AtomicU64,
+    bytes_from_guest: AtomicU64,
+    memory_pages: AtomicU64,
+    fuel: AtomicU64,
+    traps: AtomicU64,
+    tasks: AtomicU64,
+    timers: AtomicU64,
+    poll_queue: AtomicU64,
+}
+
+/// A metric and how to read it from an instance's counters
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    /// extra labels and value of each sample
+    samples: fn(&Metrics) -> Vec<(&'static str, f64)>,
+}
+
+const FAMILIES: &[Family] = &[
+    Family {
+        name: "wassup_polls_total",
+        kind: "counter",
+        help: "Calls to poll_runtime",
+        samples: |m| vec![("", get(&m.polls))],
+    },
+    Family {
+        name: "wassup_poll_seconds_total",
+        kind: "counter",
+        help: "Time spent in poll_runtime",
+        samples: |m| vec![("", get(&m.poll_nanos) / 1e9)],
+    },
+    Family {
+        name: "wassup_wakes_total",
+        kind: "counter",
+        help: "Wakeups the guest runtime requested",
+        samples: |m| vec![("", get(&m.wakes))],
+    },
+    Family {
+        name: "wassup_ipc_messages_total",
+        kind: "counter",
+        help: "Channel messages by direction",
+        samples: |m| {
+            vec![
+                (r#",direction="to_guest""#, get(&m.messages_to_guest)),
+                (r#",direction="from_guest""#, get(&m.messages_from_guest)),
+            ]
+        },
+    },
+    Family {
+        name: "wassup_ipc_bytes_total",
+        kind: "counter",
+        help: "Channel message bytes by direction",
+        samples: |m| {
+            vec![
+                (r#",direction="to_guest""#, get(&m.bytes_to_guest)),
+                (r#",direction="from_guest""#, get(&m.bytes_from_guest)),
+            ]
+        },
+    },
+    Family {
+        name: "wassup_memory_pages",
+        kind: "gauge",
+        help: "Size of the guest's memory in wasm pages, as of the last call into it",
+        samples: |m| vec![("", get(&m.memory_pages))],
+    },
+    Family {
+        name: "wassup_fuel_used_total",
+        kind: "counter",
+        help: "Function entries and loop iterations the guest ran, as of the last call into it",
+        samples: |m| vec![("", get(&m.fuel))],
+    },
+    Family {
+        name: "wassup_traps_total",
+        kind: "counter",
+        help: "Calls into the guest that trapped",
+        samples: |m| vec![("", get(&m.traps))],
+    },
+    Family {
+        name: "wassup_guest_tasks",
+        kind: "gauge",
+        help: "Tasks alive in the guest runtime",
+        samples: |m| vec![("", get(&m.tasks))],
+    },
+    Family {
+        name: "wassup_guest_timers",
+        kind: "gauge",
+        help: "Timers pending in the guest runtime",
+        samples: |m| vec![("", get(&m.timers))],
+    },
+    Family {
+        name: "wassup_guest_poll_queue",
+        kind: "gauge",
+        help: "Tasks woken but not polled yet when the guest runtime returned",
+        samples: |m| vec![("", get(&m.poll_queue))],
+    },
+];
+
+fn get(counter: &AtomicU64) -> f64 {
+    counter.load(Ordering::Relaxed) as f64
+}
+
+fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+impl Metrics {
+    /// Creates the counters of an instance and includes them in [`write_metrics`]
+    pub(crate) fn register(instance_id: u64) -> Arc<Self> {
+        let metrics = Arc::new(Self::default());
+        let mut instances = INSTANCES.lock().unwrap();
+        // a host that never exports the metrics would keep every instance it ever ran
+        instances.retain(|(_, metrics)| metrics.strong_count() > 0);
+        instances.push((instance_id, Arc::downgrade(&metrics)));
+        metrics
+    }
+
+    pub(crate) fn poll(&self, time: Duration) {
+        add(&self.polls, 1);
+        add(&self.poll_nanos, time.as_nanos() as u64);
+    }
+
+    pub(crate) fn wake(&self) {
+        add(&self.wakes, 1);
+    }
+
+    pub(crate) fn message_to_guest(&self, len: usize) {
+        add(&self.messages_to_guest, 1);
+        add(&self.bytes_to_guest, len as u64);
+    }
+
+    pub(crate) fn message_from_guest(&self, len: usize) {
+        add(&self.messages_from_guest, 1);
+        add(&self.bytes_from_guest, len as u64);
+    }
+
+    pub(crate) fn trap(&self) {
+        add(&self.traps, 1);
+    }
+
+    pub(crate) fn set_memory_pages(&self, pages: u32) {
+        self.memory_pages.store(pages as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_fuel(&self, fuel: u64) {
+        self.fuel.store(fuel, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_runtime(&self, tasks: u32, timers: u32, poll_queue: u32) {
+        self.tasks.store(tasks as u64, Ordering::Relaxed);
+        self.timers.store(timers as u64, Ordering::Relaxed);
+        self.poll_queue.store(poll_queue as u64, Ordering::Relaxed);
+    }
+
+    /// Writes the metrics of this instance alone
+    pub fn write(&self, instance_id: u64, out: impl Write) -> io::Result<()> {
+        write_families(out, &[(instance_id, self)])
+    }
+}
+
+/// Writes the metrics of every live instance, labeled with their instance id
+pub fn write_metrics(out: impl Write) -> io::Result<()> {
+    let instances = {
+        let mut instances = INSTANCES.lock().unwrap();
+        instances.retain(|(_, metrics)| metrics.strong_count() > 0);
+        instances
+            .iter()
+            .filter_map(|(id, metrics)| Some((*id, metrics.upgrade()?)))
+            .collect::<Vec<_>>()
+    };
+    let instances = instances
+        .iter()
+        .map(|(id, metrics)| (*id, &**metrics))
+        .collect::<Vec<_>>();
+    write_families(out, &instances)
+}
+
+fn write_families(mut out: impl Write, instances: &[(u64, &Metrics)]) -> io::Result<()> {
+    for family in FAMILIES {
+        writeln!(out, "# HELP {} {}", family.name, family.help)?;
+        writeln!(out, "# TYPE {} {}", family.name, family.kind)?;
+        for (id, metrics) in instances {
+            for (labels, value) in (family.samples)(metrics) {
+                writeln!(
+                    out,
+                    "{}{{instance=\"{}\"{}}} {}",
+                    family.name, id, labels, value
+                )?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registered(instance_id: u64) -> bool {
+        let instances = INSTANCES.lock().unwrap();
+        instances.iter().any(|(id, _)| *id == instance_id)
+    }
+
+    #[test]
+    fn dropped_instances_are_pruned_on_register() {
+        let dropped = Metrics::register(u64::MAX);
+        assert!(registered(u64::MAX));
+        drop(dropped);
+
+        let _live = Metrics::register(u64::MAX - 1);
+        assert!(!registered(u64::MAX));
+        assert!(registered(u64::MAX - 1));
+    }
+}
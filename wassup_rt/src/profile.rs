@@ -0,0 +1,139 @@
+use crate::backtrace::Symbols;
+use crate::trace::write_folded_stack;
+use crate::transformer::SHADOW_STACK_FRAMES;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wasmer::{Global, Memory, Value};
+
+/// Call stacks sampled from the shadow stack of an instance, see
+/// [`InstanceBuilder::profile`](crate::InstanceBuilder::profile). Functions are identified by
+/// their index in the original module.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub interval: Duration,
+    samples: HashMap<Vec<u32>, u64>,
+}
+
+/// Samples the shadow stack of one instance from a thread of its own, while the instance runs
+/// guest code
+pub(crate) struct Sampler {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    interval: Duration,
+    memory: Memory,
+    /// address of the first frame, 0 if the guest couldn't allocate the stack
+    base: u32,
+    depth: Global,
+    profile: Mutex<Profile>,
+    stopped: AtomicBool,
+}
+
+impl Profile {
+    /// How many samples were taken
+    pub fn len(&self) -> u64 {
+        self.samples.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Each sampled call stack, outermost function first, with how often it was seen
+    pub fn stacks(&self) -> impl Iterator<Item = (&[u32], u64)> + '_ {
+        self.samples
+            .iter()
+            .map(|(stack, count)| (stack.as_slice(), *count))
+    }
+
+    /// Writes the stacks in the folded format flame graph tools read, one `outer;inner samples`
+    /// line per stack. Functions are named through `symbols`.
+    pub fn write_folded(&self, mut out: impl Write, symbols: Option<&Symbols>) -> io::Result<()> {
+        let mut stacks = self.stacks().collect::<Vec<_>>();
+        stacks.sort();
+        for (stack, count) in stacks {
+            write_folded_stack(&mut out, stack, count, symbols)?;
+        }
+        out.flush()
+    }
+}
+
+impl Sampler {
+    pub fn spawn(
+        instance_id: u64,
+        interval: Duration,
+        memory: Memory,
+        base: u32,
+        depth: Global,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            interval,
+            memory,
+            base,
+            depth,
+            profile: Mutex::new(Profile {
+                interval,
+                samples: HashMap::new(),
+            }),
+            stopped: AtomicBool::new(false),
+        });
+        let sampled = shared.clone();
+        std::thread::Builder::new()
+            .name(format!("wassup-sampler-{}", instance_id))
+            .spawn(move || {
+                while !sampled.stopped.load(Ordering::Acquire) {
+                    std::thread::sleep(sampled.interval);
+                    sampled.sample();
+                }
+            })
+            .expect("failed to spawn sampler thread");
+
+        Self { shared }
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.shared.profile.lock().unwrap().clone()
+    }
+
+    /// Drops the frames a trap left behind, once the call into the guest returned
+    pub fn reset(&self) {
+        let _ = self.shared.depth.set(Value::I32(0));
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Release);
+    }
+}
+
+impl Shared {
+    fn sample(&self) {
+        let depth = match self.depth.get() {
+            Value::I32(depth) if depth > 0 && self.base != 0 => {
+                (depth as u32).min(SHADOW_STACK_FRAMES)
+            }
+            // the host is running
+            _ => return,
+        };
+        // the guest keeps running, a frame may be overwritten while the stack is read
+        let view = self.memory.view::<u32>();
+        let frames = view.atomically();
+        let base = self.base as usize / 4;
+        let stack = frames[base..base + depth as usize]
+            .iter()
+            .map(|frame| frame.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        *self
+            .profile
+            .lock()
+            .unwrap()
+            .samples
+            .entry(stack)
+            .or_default() += 1;
+    }
+}
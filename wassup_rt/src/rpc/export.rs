@@ -0,0 +1,139 @@
+use super::codec::{self, Codec, CodecError, DefaultCodec};
+use crate::instance::{GuestInstance, Status};
+use crate::wasi_api::WasiEnv;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use wasmer::{ExportError, RuntimeError, WasmPtr};
+
+/// A call to a `#[wassup_std::export]` handler, the handler runs as the guest gets polled
+pub struct PendingCall<Resp> {
+    id: u64,
+    env: WasiEnv,
+    _phantom: PhantomData<fn() -> Resp>,
+}
+
+#[derive(Debug)]
+pub enum CallError {
+    /// The guest has no handler with that name
+    Export(ExportError),
+    Codec(CodecError),
+    /// The handler couldn't decode its arguments or encode its output
+    Handler(String),
+    Runtime(RuntimeError),
+    /// The guest runtime shut down before the handler finished
+    Exited(u32),
+}
+
+impl<Resp: DeserializeOwned> PendingCall<Resp> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Takes the handler's output once it finished
+    pub fn try_take(&self) -> Option<Result<Resp, CallError>> {
+        let (_, completion) = self.env.state.completions.remove(&self.id)?;
+        Some(match completion.status {
+            0 => DefaultCodec::decode(&completion.result).map_err(CallError::Codec),
+            _ => Err(CallError::Handler(
+                String::from_utf8_lossy(&completion.result).into_owned(),
+            )),
+        })
+    }
+}
+
+impl<Resp> Drop for PendingCall<Resp> {
+    fn drop(&mut self) {
+        self.env.state.completions.remove(&self.id);
+    }
+}
+
+impl GuestInstance {
+    /// Starts the guest's `#[wassup_std::export]` handler `name`. A handler with several
+    /// arguments takes them as a tuple.
+    pub fn call_export<Args, Resp>(&self, name: &str, args: &Args) -> Result<PendingCall<Resp>, CallError>
+    where
+        Args: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let exports = &self.instance().exports;
+        let entry = exports
+            .get_native_function::<(u64, u32, u32), ()>(&format!("__wassup_export:{}", name))
+            .map_err(CallError::Export)?;
+        let alloc = exports
+            .get_native_function::<u32, u32>("__wassup_alloc")
+            .map_err(CallError::Export)?;
+        let guest_codec = exports
+            .get_native_function::<(), u32>("__wassup_codec")
+            .map_err(CallError::Export)?;
+        let guest_codec = guest_codec.call().map_err(CallError::Runtime)?;
+        codec::check_codec::<DefaultCodec>(guest_codec).map_err(CallError::Codec)?;
+
+        let mut bytes = vec![];
+        DefaultCodec::encode(args, &mut bytes).map_err(CallError::Codec)?;
+        let ptr = alloc.call(bytes.len() as u32).map_err(CallError::Runtime)?;
+        if !self.env().write_bytes(WasmPtr::new(ptr), &bytes) {
+            return Err(CallError::Runtime(RuntimeError::new("guest allocation out of bounds")));
+        }
+
+        let id = self.env().state.next_call_id.fetch_add(1, Ordering::Relaxed);
+        let call = PendingCall {
+            id,
+            env: self.env().clone(),
+            _phantom: PhantomData,
+        };
+        entry
+            .call(id, ptr, bytes.len() as u32)
+            .map_err(CallError::Runtime)?;
+
+        Ok(call)
+    }
+
+    /// Calls the handler `name` and polls the guest until it finished
+    pub fn invoke<Args, Resp>(&self, name: &str, args: &Args) -> Result<Resp, CallError>
+    where
+        Args: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let call = self.call_export::<Args, Resp>(name, args)?;
+        loop {
+            match self.poll().map_err(CallError::Runtime)? {
+                Status::Exited(exit_code) => return Err(CallError::Exited(exit_code)),
+                Status::Pending(sleep_time) => {
+                    if let Some(result) = call.try_take() {
+                        return result;
+                    }
+                    std::thread::sleep(sleep_time);
+                }
+            }
+        }
+    }
+}
+
+impl Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Export(err) => write!(f, "no such guest handler: {}", err),
+            CallError::Codec(err) => Display::fmt(err, f),
+            CallError::Handler(msg) => write!(f, "guest handler failed: {}", msg),
+            CallError::Runtime(err) => Display::fmt(err, f),
+            CallError::Exited(exit_code) => {
+                write!(f, "guest runtime shut down with exit code {}", exit_code)
+            }
+        }
+    }
+}
+
+impl Error for CallError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CallError::Export(err) => Some(err),
+            CallError::Codec(err) => Some(err),
+            CallError::Runtime(err) => Some(err),
+            CallError::Handler(_) | CallError::Exited(_) => None,
+        }
+    }
+}
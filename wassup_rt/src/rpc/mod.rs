@@ -0,0 +1,118 @@
+//! Serves calls made with the guest's `TypedIpc` and calls `#[wassup_std::export]` handlers.
+//!
+//! Every `TypedIpc` frame is a little endian `u64` request id followed by the encoded payload, responses
+//! carry the id of their request.
+
+mod export;
+
+pub use export::{CallError, PendingCall};
+pub use wassup_codec as codec;
+
+use crate::wasi_api::{Ipc, TrySendError};
+use codec::{Codec, CodecError, DefaultCodec, HANDSHAKE_ID};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+
+const HEADER_LEN: usize = 8;
+
+/// The host end of a `TypedIpc<Req, Resp>`
+pub struct RpcServer<Req, Resp, C = DefaultCodec> {
+    ipc: Ipc,
+    _phantom: PhantomData<fn(Resp, C) -> Req>,
+}
+
+/// Identifies the request a response belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(pub u64);
+
+#[derive(Debug)]
+pub enum RpcError {
+    Codec(CodecError),
+    /// The channel's queue to the guest is full
+    Full,
+    Closed,
+}
+
+impl<Req, Resp, C> RpcServer<Req, Resp, C>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    C: Codec,
+{
+    pub fn new(ipc: Ipc) -> Self {
+        Self {
+            ipc,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn ipc(&self) -> &Ipc {
+        &self.ipc
+    }
+
+    /// Takes the next request, frames without a request id are skipped. The guest's handshake
+    /// fails with [`CodecMismatch`](codec::CodecMismatch) if it uses another codec.
+    pub fn try_next(&self) -> Option<Result<(RequestId, Req), CodecError>> {
+        loop {
+            let frame = self.ipc.try_recv()?;
+            if frame.len() < HEADER_LEN {
+                log::warn!("dropped rpc frame without request id on channel {}", self.ipc.id());
+                continue;
+            }
+            let id = RequestId(u64::from_le_bytes(frame[..HEADER_LEN].try_into().unwrap()));
+            if id.0 == HANDSHAKE_ID {
+                let peer = frame[HEADER_LEN..]
+                    .try_into()
+                    .map_or(0, u32::from_le_bytes);
+                match codec::check_codec::<C>(peer) {
+                    Ok(()) => continue,
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            return Some(C::decode(&frame[HEADER_LEN..]).map(|req| (id, req)));
+        }
+    }
+
+    /// Queues the response to request `id`, it reaches the guest on its next poll
+    pub fn respond(&self, id: RequestId, resp: &Resp) -> Result<(), RpcError> {
+        let mut frame = id.0.to_le_bytes().to_vec();
+        C::encode(resp, &mut frame).map_err(RpcError::Codec)?;
+        self.ipc.try_send(frame.into()).map_err(|err| match err {
+            TrySendError::Full(_) => RpcError::Full,
+            TrySendError::Closed(_) => RpcError::Closed,
+        })
+    }
+
+    /// Answers every pending request with `handler`, returns how many were served
+    pub fn serve_pending(&self, mut handler: impl FnMut(Req) -> Resp) -> Result<usize, RpcError> {
+        let mut served = 0;
+        while let Some(req) = self.try_next() {
+            let (id, req) = req.map_err(RpcError::Codec)?;
+            self.respond(id, &handler(req))?;
+            served += 1;
+        }
+        Ok(served)
+    }
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Codec(err) => Display::fmt(err, f),
+            RpcError::Full => f.write_str("channel to the guest is full"),
+            RpcError::Closed => f.write_str("channel is closed"),
+        }
+    }
+}
+
+impl Error for RpcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RpcError::Codec(err) => Some(err),
+            RpcError::Full | RpcError::Closed => None,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::cache::ModuleCache;
+use crate::compiler::Backend;
+use crate::error::Error;
+use crate::transformer::ModuleTransformer;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use wasmer::{Module, Store};
+
+/// Compiles and caches guest modules, instances are created from them with
+/// [`InstanceBuilder`](crate::InstanceBuilder).
+pub struct Runtime {
+    store: Store,
+    transformer: Arc<ModuleTransformer>,
+    cache: ModuleCache,
+    /// the transformer instruments one module at a time
+    compile_lock: Mutex<()>,
+}
+
+impl Runtime {
+    pub fn new(backend: Backend) -> Self {
+        Self::with_transformer(backend, ModuleTransformer::default())
+    }
+
+    /// Instruments the modules with `transformer`, e.g. to trace calls
+    pub fn with_transformer(backend: Backend, transformer: ModuleTransformer) -> Self {
+        let cache = ModuleCache::from_env(format!("{} {:?}", backend.settings(), transformer));
+        Self::with_cache(backend, transformer, cache)
+    }
+
+    pub fn with_cache(backend: Backend, transformer: ModuleTransformer, cache: ModuleCache) -> Self {
+        let transformer = Arc::new(transformer);
+        Self {
+            store: backend.make_store(transformer.clone()),
+            transformer,
+            cache,
+            compile_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn cache(&self) -> &ModuleCache {
+        &self.cache
+    }
+
+    /// Loads a module, going through the compilation cache
+    pub fn load(&self, wasm: &[u8]) -> Result<Module, Error> {
+        let _compiling = self.compile_lock.lock().unwrap();
+        self.transformer.prepare(wasm);
+        Ok(self.cache.load(&self.store, wasm)?)
+    }
+
+    pub fn load_file(&self, path: impl AsRef<Path>) -> Result<Module, Error> {
+        self.load(&std::fs::read(path)?)
+    }
+
+    /// Compiles a module and stores it in the cache, replacing an existing entry
+    pub fn compile(&self, wasm: &[u8]) -> Result<Module, Error> {
+        let _compiling = self.compile_lock.lock().unwrap();
+        self.transformer.prepare(wasm);
+        Ok(self.cache.compile(&self.store, wasm)?)
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new(Backend::default())
+    }
+}
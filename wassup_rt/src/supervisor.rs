@@ -0,0 +1,528 @@
+//! Keeps a set of guest instances running in one host thread, restarting them when they fail.
+//!
+//! ```no_run
+//! use wassup::supervisor::{RestartPolicy, Supervisor};
+//! use wassup::{InstanceBuilder, Runtime};
+//!
+//! let runtime = Runtime::default();
+//! let module = runtime.load_file("service.wasm").unwrap();
+//! let mut supervisor = Supervisor::new();
+//! supervisor.add("service", RestartPolicy::on_failure(), || {
+//!     InstanceBuilder::new(&module).build()
+//! });
+//! for (name, exit) in supervisor.run() {
+//!     println!("{}: {}", name, exit);
+//! }
+//! ```
+
+use crate::error::Error;
+use crate::instance::{GuestInstance, Status};
+use crate::wasi_api::{GuestPanic, Panicked};
+use crate::watchdog::DeadlineExceeded;
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use wasmer::RuntimeError;
+use wasmer_types::TrapCode;
+
+/// Longest a child waits between polls, even if its runtime only waits for a wakeup, so messages
+/// sent to it from other threads get delivered
+const IDLE_POLL: Duration = Duration::from_millis(10);
+
+/// When a child gets restarted
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Restart {
+    Never,
+    /// After it trapped, panicked, exited with a non-zero code or failed to start
+    OnFailure,
+    /// Whenever it stopped, even with exit code 0
+    Always,
+}
+
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    pub restart: Restart,
+    /// Delay before a restart, doubled for every other restart within `window`
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// A child restarted more often than this within `window` is given up on
+    pub max_restarts: u32,
+    pub window: Duration,
+}
+
+/// Why a child stopped
+#[derive(Debug)]
+pub enum Exit {
+    /// The guest runtime shut down or called `proc_exit` with the exit code
+    Code(u32),
+    /// The guest panicked, which aborts with an `unreachable` trap. The error is [`Panicked`] if
+    /// the panic hook reported the panic.
+    Panic(RuntimeError),
+    /// Any other trap, including [`DeadlineExceeded`](crate::DeadlineExceeded) and
+    /// [`MemoryLimitExceeded`](crate::MemoryLimitExceeded)
+    Trap(RuntimeError),
+    /// The instance couldn't be created
+    Start(Error),
+}
+
+pub struct Supervisor<'a> {
+    children: Vec<Child<'a>>,
+}
+
+struct Child<'a> {
+    name: String,
+    policy: RestartPolicy,
+    factory: Box<dyn FnMut() -> Result<GuestInstance, Error> + 'a>,
+    state: ChildState,
+    /// when the child was restarted, within the policy's window
+    restarts: VecDeque<Instant>,
+}
+
+enum ChildState {
+    Starting {
+        at: Instant,
+    },
+    Running {
+        /// boxed, an instance is much larger than the other states
+        instance: Box<GuestInstance>,
+        next_poll: Instant,
+    },
+    Stopped(Exit),
+}
+
+impl RestartPolicy {
+    pub fn never() -> Self {
+        Self::new(Restart::Never)
+    }
+
+    pub fn on_failure() -> Self {
+        Self::new(Restart::OnFailure)
+    }
+
+    pub fn always() -> Self {
+        Self::new(Restart::Always)
+    }
+
+    /// Waits 100ms before the first restart up to 30s, allows 5 restarts per minute
+    pub fn new(restart: Restart) -> Self {
+        Self {
+            restart,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Duration, max_backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn max_restarts(mut self, max_restarts: u32, window: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.window = window;
+        self
+    }
+
+    fn applies_to(&self, exit: &Exit) -> bool {
+        match self.restart {
+            Restart::Never => false,
+            Restart::OnFailure => exit.is_failure(),
+            Restart::Always => true,
+        }
+    }
+
+    /// Delay before the next restart, given the restarts within the window
+    fn delay(&self, restarts: usize) -> Duration {
+        let factor = 1u32.checked_shl(restarts as u32).unwrap_or(u32::MAX);
+        self.backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self::on_failure()
+    }
+}
+
+impl Exit {
+    /// `None` while the guest runtime is still running
+    fn from_result(result: Result<Status, RuntimeError>) -> Option<Self> {
+        match result {
+            Ok(Status::Pending(_)) => None,
+            Ok(Status::Exited(exit_code)) => Some(Exit::Code(exit_code)),
+            Err(err)
+                if err.is::<Panicked>()
+                    || err.clone().to_trap() == Some(TrapCode::UnreachableCodeReached) =>
+            {
+                Some(Exit::Panic(err))
+            }
+            Err(err) => Some(Exit::Trap(err)),
+        }
+    }
+
+    /// What the guest's panic hook reported, if it panicked
+    pub fn panic(&self) -> Option<GuestPanic> {
+        match self {
+            // `downcast` only works on the last clone of the error
+            Exit::Panic(err) => std::error::Error::source(err)
+                .and_then(|source| source.downcast_ref::<Panicked>())
+                .map(|panicked| panicked.panic.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, Exit::Code(0))
+    }
+}
+
+impl<'a> Supervisor<'a> {
+    pub fn new() -> Self {
+        Self { children: vec![] }
+    }
+
+    /// Adds a child, `factory` creates its instance for every (re)start and `_start` is called on
+    /// it
+    pub fn add<F>(
+        &mut self,
+        name: impl Into<String>,
+        policy: RestartPolicy,
+        factory: F,
+    ) -> &mut Self
+    where
+        F: FnMut() -> Result<GuestInstance, Error> + 'a,
+    {
+        self.children.push(Child {
+            name: name.into(),
+            policy,
+            factory: Box::new(factory),
+            state: ChildState::Starting { at: Instant::now() },
+            restarts: VecDeque::new(),
+        });
+        self
+    }
+
+    /// Polls the children until none of them is left running or waiting for a restart, returns
+    /// how each of them stopped for the last time
+    pub fn run(mut self) -> Vec<(String, Exit)> {
+        while let Some(wakeup) = self.step() {
+            let now = Instant::now();
+            if wakeup > now {
+                std::thread::sleep(wakeup - now);
+            }
+        }
+
+        self.children
+            .into_iter()
+            .map(|child| match child.state {
+                ChildState::Stopped(exit) => (child.name, exit),
+                _ => unreachable!("all children stopped"),
+            })
+            .collect()
+    }
+
+    /// Starts and polls the children that are due, returns when the next one is due or `None`
+    /// once all of them stopped for good
+    pub fn step(&mut self) -> Option<Instant> {
+        let now = Instant::now();
+        let mut wakeup = None::<Instant>;
+        for child in &mut self.children {
+            let next = match child.step(now) {
+                Some(next) => next,
+                None => continue,
+            };
+            wakeup = Some(wakeup.map_or(next, |wakeup| wakeup.min(next)));
+        }
+        wakeup
+    }
+}
+
+impl Default for Supervisor<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Child<'_> {
+    /// Returns when the child is due next, `None` if it stopped for good
+    fn step(&mut self, now: Instant) -> Option<Instant> {
+        let result = match &self.state {
+            ChildState::Starting { at } if *at > now => return Some(*at),
+            ChildState::Starting { .. } => match (self.factory)() {
+                Ok(instance) => {
+                    log::info!("child `{}` started", self.name);
+                    let result = instance.start();
+                    self.state = ChildState::Running {
+                        instance: Box::new(instance),
+                        next_poll: now,
+                    };
+                    result
+                }
+                Err(err) => return self.stopped(Exit::Start(err)),
+            },
+            ChildState::Running { next_poll, .. } if *next_poll > now => return Some(*next_poll),
+            ChildState::Running { instance, .. } => instance.poll(),
+            ChildState::Stopped(_) => return None,
+        };
+
+        match result {
+            Ok(Status::Pending(sleep_time)) => {
+                let next = Instant::now() + sleep_time.min(IDLE_POLL);
+                if let ChildState::Running { next_poll, .. } = &mut self.state {
+                    *next_poll = next;
+                }
+                Some(next)
+            }
+            Err(err) => {
+                if let ChildState::Running { instance, .. } = &self.state {
+                    log::warn!(
+                        "child `{}` trapped: {}",
+                        self.name,
+                        instance.backtrace(&err)
+                    );
+                    if err.is::<DeadlineExceeded>() {
+                        log_tasks(&self.name, instance);
+                    }
+                }
+                self.stopped(Exit::from_result(Err(err)).unwrap())
+            }
+            result => self.stopped(Exit::from_result(result).unwrap()),
+        }
+    }
+
+    /// Drops the instance and schedules a restart if the policy asks for one
+    fn stopped(&mut self, exit: Exit) -> Option<Instant> {
+        let now = Instant::now();
+        if let Exit::Panic(_) | Exit::Trap(_) = exit {
+            // logged with its backtrace already
+        } else if exit.is_failure() {
+            log::warn!("child `{}` failed: {}", self.name, exit);
+        } else {
+            log::info!("child `{}` stopped: {}", self.name, exit);
+        }
+
+        if !self.policy.applies_to(&exit) {
+            self.state = ChildState::Stopped(exit);
+            return None;
+        }
+
+        while let Some(&restart) = self.restarts.front() {
+            if now.duration_since(restart) <= self.policy.window {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.policy.max_restarts as usize {
+            log::error!(
+                "child `{}` restarted {} times within {:?}, giving up on it",
+                self.name,
+                self.restarts.len(),
+                self.policy.window,
+            );
+            self.state = ChildState::Stopped(exit);
+            return None;
+        }
+
+        let at = now + self.policy.delay(self.restarts.len());
+        self.restarts.push_back(now);
+        self.state = ChildState::Starting { at };
+        Some(at)
+    }
+}
+
+/// Shows which task kept the child from yielding
+fn log_tasks(name: &str, instance: &GuestInstance) {
+    match instance.tasks() {
+        Ok(tasks) => {
+            log::warn!("child `{}` had {} tasks:", name, tasks.len());
+            for task in tasks {
+                log::warn!("  {}", task);
+            }
+        }
+        Err(err) => log::debug!("child `{}` couldn't list its tasks: {}", name, err),
+    }
+}
+
+impl Display for Restart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Restart::Never => "never",
+            Restart::OnFailure => "on-failure",
+            Restart::Always => "always",
+        })
+    }
+}
+
+impl FromStr for Restart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Restart::Never),
+            "on-failure" => Ok(Restart::OnFailure),
+            "always" => Ok(Restart::Always),
+            _ => Err(format!(
+                "unknown restart policy `{}`, available: never, on-failure, always",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exit::Code(exit_code) => write!(f, "exited with code {}", exit_code),
+            Exit::Panic(err) => write!(f, "panicked: {}", err),
+            Exit::Trap(err) => write!(f, "trapped: {}", err),
+            Exit::Start(err) => write!(f, "failed to start: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Backend, ModuleTransformer};
+    use std::sync::Arc;
+    use wasmer::{imports, Instance, Module};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// The error of calling a function with `body`
+    fn trap(body: &str) -> RuntimeError {
+        let store = Backend::default().make_store(Arc::new(ModuleTransformer::default()));
+        let wat = format!(r#"(module (func (export "f") {}))"#, body);
+        let module = Module::new(&store, wat).unwrap();
+        let instance = Instance::new(&module, &imports! {}).unwrap();
+        let f = instance.exports.get_native_function::<(), ()>("f").unwrap();
+        f.call().unwrap_err()
+    }
+
+    fn failing_child(policy: RestartPolicy) -> Child<'static> {
+        Child {
+            name: "child".to_string(),
+            policy,
+            factory: Box::new(|| Err(Error::Io(std::io::ErrorKind::NotFound.into()))),
+            state: ChildState::Starting { at: Instant::now() },
+            restarts: VecDeque::new(),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RestartPolicy::on_failure().backoff(100 * MS, 1000 * MS);
+        let delays = (0..6).map(|restarts| policy.delay(restarts)).collect::<Vec<_>>();
+        assert_eq!(delays, [100 * MS, 200 * MS, 400 * MS, 800 * MS, 1000 * MS, 1000 * MS]);
+        // the factor overflows
+        assert_eq!(policy.delay(40), 1000 * MS);
+        assert_eq!(policy.delay(usize::MAX), 1000 * MS);
+    }
+
+    #[test]
+    fn restart_applies_to_exit_codes() {
+        for (restart, on_success, on_failure) in [
+            (Restart::Never, false, false),
+            (Restart::OnFailure, false, true),
+            (Restart::Always, true, true),
+        ] {
+            let policy = RestartPolicy::new(restart);
+            assert_eq!(policy.applies_to(&Exit::Code(0)), on_success, "{}", restart);
+            assert_eq!(policy.applies_to(&Exit::Code(1)), on_failure, "{}", restart);
+            assert_eq!(policy.applies_to(&Exit::Trap(trap("unreachable"))), on_failure);
+        }
+    }
+
+    #[test]
+    fn exit_from_result() {
+        assert!(Exit::from_result(Ok(Status::Pending(MS))).is_none());
+        assert!(matches!(
+            Exit::from_result(Ok(Status::Exited(0))),
+            Some(Exit::Code(0))
+        ));
+        assert!(matches!(
+            Exit::from_result(Ok(Status::Exited(3))),
+            Some(Exit::Code(3))
+        ));
+        assert!(matches!(
+            Exit::from_result(Err(trap("unreachable"))),
+            Some(Exit::Panic(_))
+        ));
+
+        let panic = GuestPanic {
+            message: "oops".to_string(),
+            file: "src/main.rs".to_string(),
+            line: 1,
+            column: 1,
+            task: None,
+        };
+        let panicked = RuntimeError::user(Box::new(Panicked {
+            panic,
+            trap: trap("unreachable"),
+        }));
+        let exit = Exit::from_result(Err(panicked)).unwrap();
+        assert_eq!(exit.panic().unwrap().message, "oops");
+
+        let div_by_zero = trap("i32.const 1 i32.const 0 i32.div_u drop");
+        assert!(matches!(
+            Exit::from_result(Err(div_by_zero)),
+            Some(Exit::Trap(_))
+        ));
+        assert!(matches!(
+            Exit::from_result(Err(RuntimeError::new("host error"))),
+            Some(Exit::Trap(_))
+        ));
+    }
+
+    #[test]
+    fn gives_up_after_max_restarts() {
+        let mut starts = 0;
+        let mut supervisor = Supervisor::new();
+        let policy = RestartPolicy::on_failure()
+            .backoff(MS, MS)
+            .max_restarts(3, Duration::from_secs(60));
+        supervisor.add("child", policy, || {
+            starts += 1;
+            Err(Error::Io(std::io::ErrorKind::NotFound.into()))
+        });
+        let exits = supervisor.run();
+        assert!(matches!(exits[..], [(_, Exit::Start(_))]));
+        assert_eq!(starts, 4);
+    }
+
+    #[test]
+    fn restarts_leave_the_window() {
+        let window = 20 * MS;
+        let mut child = failing_child(RestartPolicy::on_failure().max_restarts(2, window));
+        assert!(child.stopped(Exit::Code(1)).is_some());
+        assert!(child.stopped(Exit::Code(1)).is_some());
+        assert!(child.stopped(Exit::Code(1)).is_none());
+
+        let mut child = failing_child(RestartPolicy::on_failure().max_restarts(2, window));
+        assert!(child.stopped(Exit::Code(1)).is_some());
+        assert!(child.stopped(Exit::Code(1)).is_some());
+        std::thread::sleep(window * 2);
+        assert!(child.stopped(Exit::Code(1)).is_some());
+        assert_eq!(child.restarts.len(), 1);
+    }
+
+    #[test]
+    fn never_and_success_stop_for_good() {
+        let mut child = failing_child(RestartPolicy::never());
+        assert!(child.stopped(Exit::Code(1)).is_none());
+        assert!(child.restarts.is_empty());
+
+        let mut child = failing_child(RestartPolicy::on_failure());
+        assert!(child.stopped(Exit::Code(0)).is_none());
+
+        let mut child = failing_child(RestartPolicy::always());
+        assert!(child.stopped(Exit::Code(0)).is_some());
+    }
+}
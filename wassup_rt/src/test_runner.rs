@@ -0,0 +1,114 @@
+use crate::{Coverage, InstanceBuilder, Panicked, Symbols};
+use std::sync::Arc;
+use wasmer::{ExternType, Module};
+use wasmer_types::TrapCode;
+
+/// Prefix of the exports generated by `#[wassup_std::test]`
+const TEST_PREFIX: &str = "__wassup_test:";
+
+enum Outcome {
+    Ok,
+    Exited(u32),
+    /// holds the panic message and backtrace
+    Panicked(String),
+    Failed(String),
+}
+
+/// Runs every exported test whose name contains `filter` in a fresh instance and prints a
+/// `cargo test` like report. Returns whether all tests passed, `symbols` adds source locations to
+/// the backtraces of failed tests. The blocks every test ran are added to `coverage`.
+pub fn run_tests(
+    module: &Module,
+    symbols: Option<Arc<Symbols>>,
+    filter: Option<&str>,
+    mut coverage: Option<&mut Coverage>,
+) -> bool {
+    let mut tests = module
+        .exports()
+        .filter(|export| matches!(export.ty(), ExternType::Function(_)))
+        .filter_map(|export| {
+            let name = export.name().strip_prefix(TEST_PREFIX)?.to_string();
+            Some((export.name().to_string(), name))
+        })
+        .filter(|(_, name)| filter.map(|filter| name.contains(filter)).unwrap_or(true))
+        .collect::<Vec<_>>();
+    tests.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    println!();
+    println!("running {} tests", tests.len());
+
+    let mut failures = vec![];
+    for (export, name) in &tests {
+        match run_test(module, symbols.clone(), export, coverage.as_deref_mut()) {
+            Outcome::Ok => println!("test {} ... ok", name),
+            Outcome::Exited(code) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, format!("exited with code {}", code)));
+            }
+            Outcome::Panicked(err) => {
+                println!("test {} ... FAILED (panicked)", name);
+                failures.push((name, err));
+            }
+            Outcome::Failed(err) => {
+                println!("test {} ... FAILED", name);
+                failures.push((name, err));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!();
+        println!("failures:");
+        for (name, err) in &failures {
+            println!();
+            println!("---- {} ----", name);
+            println!("{}", err);
+        }
+    }
+
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failures.len(),
+        failures.len(),
+    );
+
+    failures.is_empty()
+}
+
+fn run_test(
+    module: &Module,
+    symbols: Option<Arc<Symbols>>,
+    export: &str,
+    coverage: Option<&mut Coverage>,
+) -> Outcome {
+    let mut builder = InstanceBuilder::new(module);
+    if let Some(symbols) = symbols {
+        builder = builder.symbols(symbols);
+    }
+    let instance = match builder.build() {
+        Ok(instance) => instance,
+        Err(err) => return Outcome::Failed(err.to_string()),
+    };
+    let outcome = match instance.run_entry(export) {
+        Ok(0) => Outcome::Ok,
+        Ok(code) => Outcome::Exited(code),
+        // a guest panic aborts, which shows up as an `unreachable` trap
+        Err(err)
+            if err.is::<Panicked>()
+                || err.clone().to_trap() == Some(TrapCode::UnreachableCodeReached) =>
+        {
+            Outcome::Panicked(instance.backtrace(&err).to_string())
+        }
+        Err(err) => Outcome::Failed(instance.backtrace(&err).to_string()),
+    };
+
+    if let Some(coverage) = coverage {
+        match instance.coverage(coverage.map()) {
+            Ok(run) => coverage.merge(&run),
+            Err(err) => log::warn!("no coverage for {}: {}", export, err),
+        }
+    }
+    outcome
+}
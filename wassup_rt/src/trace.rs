@@ -0,0 +1,103 @@
+use crate::backtrace::Symbols;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// The calls of one instance, recorded through the hooks of
+/// [`ModuleTransformer::trace_calls`](crate::ModuleTransformer::trace_calls). Functions are
+/// identified by their index in the original module.
+#[derive(Debug, Clone, Default)]
+pub struct CallTrace {
+    stack: Vec<u32>,
+    /// when the innermost frame last got to run
+    since: Option<Instant>,
+    /// time spent in each stack, not counting its callees
+    stacks: HashMap<Vec<u32>, Duration>,
+    calls: HashMap<u32, u64>,
+}
+
+impl CallTrace {
+    pub(crate) fn enter(&mut self, function: u32) {
+        self.charge();
+        self.stack.push(function);
+        *self.calls.entry(function).or_default() += 1;
+    }
+
+    pub(crate) fn exit(&mut self, function: u32) {
+        self.charge();
+        // frames left without their exit hook are dropped along with it
+        if let Some(pos) = self.stack.iter().rposition(|&f| f == function) {
+            self.stack.truncate(pos);
+        }
+    }
+
+    /// The call into the guest returned to the host, possibly with a trap in between
+    pub(crate) fn unwind(&mut self) {
+        self.charge();
+        self.stack.clear();
+        self.since = None;
+    }
+
+    fn charge(&mut self) {
+        let now = Instant::now();
+        if let (Some(since), false) = (self.since, self.stack.is_empty()) {
+            match self.stacks.get_mut(self.stack.as_slice()) {
+                Some(time) => *time += now - since,
+                None => {
+                    self.stacks.insert(self.stack.clone(), now - since);
+                }
+            }
+        }
+        self.since = Some(now);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// How often the function was called
+    pub fn calls(&self, function: u32) -> u64 {
+        self.calls.get(&function).copied().unwrap_or(0)
+    }
+
+    /// Each call stack, outermost function first, with the time spent in its innermost function
+    pub fn stacks(&self) -> impl Iterator<Item = (&[u32], Duration)> + '_ {
+        self.stacks
+            .iter()
+            .map(|(stack, time)| (stack.as_slice(), *time))
+    }
+
+    /// Writes the stacks in the folded format flame graph tools read, one `outer;inner micros`
+    /// line per stack. Functions are named through `symbols`.
+    pub fn write_folded(&self, mut out: impl Write, symbols: Option<&Symbols>) -> io::Result<()> {
+        let mut stacks = self.stacks().collect::<Vec<_>>();
+        stacks.sort();
+        for (stack, time) in stacks {
+            write_folded_stack(&mut out, stack, time.as_micros() as u64, symbols)?;
+        }
+        out.flush()
+    }
+}
+
+/// Writes one line of folded stacks, `;` separated frames and the value
+pub(crate) fn write_folded_stack(
+    out: &mut impl Write,
+    stack: &[u32],
+    value: u64,
+    symbols: Option<&Symbols>,
+) -> io::Result<()> {
+    for (i, &function) in stack.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b";")?;
+        }
+        match symbols.and_then(|symbols| symbols.function_name(function)) {
+            // `;` separates frames, it shows up in demangled array types
+            Some(name) => {
+                let name = format!("{:#}", rustc_demangle::demangle(name));
+                out.write_all(name.replace(';', ",").as_bytes())?;
+            }
+            None => write!(out, "<wasm function {}>", function)?,
+        }
+    }
+    writeln!(out, " {}", value)
+}
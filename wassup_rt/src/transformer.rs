@@ -1,30 +1,619 @@
-use wasmer::wasmparser::Operator;
+use crate::coverage::CoverageMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use wasmer::wasmparser::{MemoryImmediate, Operator, Type as WpType, TypeOrFuncType};
 use wasmer::{FunctionMiddleware, MiddlewareError, MiddlewareReaderState, ModuleMiddleware};
-use wasmer_types::{LocalFunctionIndex, ModuleInfo};
+use wasmer_types::{
+    ExportIndex, FunctionIndex, FunctionType, GlobalIndex, GlobalInit, GlobalType, ImportIndex,
+    LocalFunctionIndex, MemoryIndex, ModuleInfo, Mutability, Type, WASM_PAGE_SIZE,
+};
 
-#[derive(Debug, loupe::MemoryUsage)]
-pub struct ModuleTransformer {}
+/// Exported `i32` global, the guest traps at the next function entry or loop iteration once the
+/// host sets it to a non-zero value
+pub const INTERRUPT_GLOBAL: &str = "__wassup_interrupt";
+
+/// Exported `i64` global, the fuel the guest used: one unit per function entry and loop iteration
+pub const FUEL_GLOBAL: &str = "__wassup_fuel";
+
+/// Import module of the host hooks the instrumentation calls
+pub const HOOK_MODULE: &str = "__wassup";
+
+/// Hooks called with the function's index in the original module on entry and exit of every
+/// function, when call tracing is enabled
+pub const TRACE_ENTER: &str = "trace_enter";
+pub const TRACE_EXIT: &str = "trace_exit";
+
+/// The guest's memory, exported again for the shadow stack and the coverage counters
+pub const INSTRUMENTED_MEMORY: &str = "__wassup_memory";
+
+/// Guest export the host allocates the shadow stack and the coverage counters through after
+/// instantiation, the instrumentation leaves them alone while their address is still 0
+pub const INSTRUMENTATION_ALLOC: &str = "__wassup_alloc";
+
+/// Exported `i32` globals of the shadow stack, the address of its frames in the exported memory
+/// and how many functions are on it. Each frame is the `u32` index of a function in the original
+/// module, outermost first.
+pub const SHADOW_STACK_GLOBAL: &str = "__wassup_shadow_stack";
+pub const SHADOW_DEPTH_GLOBAL: &str = "__wassup_shadow_depth";
+
+/// Exported `i32` globals, the address of the `u32` counters of the blocks in a [`CoverageMap`]
+/// and how many there are
+pub const COVERAGE_GLOBAL: &str = "__wassup_coverage";
+pub const COVERAGE_LEN_GLOBAL: &str = "__wassup_coverage_len";
+
+/// Frames the shadow stack has room for, deeper calls are counted but not recorded
+pub const SHADOW_STACK_FRAMES: u32 = (WASM_PAGE_SIZE / 4) as u32;
+
+/// Instruments every function of the modules a store compiles. Modules have to be compiled one at
+/// a time, as the functions learn their module's globals through the transformer.
+#[derive(loupe::MemoryUsage)]
+pub struct ModuleTransformer {
+    trace_calls: bool,
+    shadow_stack: bool,
+    coverage: bool,
+    /// blocks of the module about to be compiled, from [`ModuleTransformer::prepare`]
+    #[loupe(skip)]
+    coverage_map: Mutex<Option<Arc<CoverageMap>>>,
+    module: Mutex<Option<Instrumented>>,
+}
+
+/// What the functions of the module being compiled need to know
+#[derive(Debug, Clone, loupe::MemoryUsage)]
+struct Instrumented {
+    /// imported by the original module, the function ids start after them
+    num_imported_functions: u32,
+    interrupt: GlobalIndex,
+    fuel: GlobalIndex,
+    trace: Option<TraceHooks>,
+    shadow_stack: Option<ShadowStack>,
+    coverage: Option<Counters>,
+    /// block type wrapping the body of each of the module's own functions, if they have exit
+    /// instrumentation
+    #[loupe(skip)]
+    bodies: Arc<Vec<Option<TypeOrFuncType>>>,
+}
+
+#[derive(Debug, Copy, Clone, loupe::MemoryUsage)]
+struct TraceHooks {
+    enter: FunctionIndex,
+    exit: FunctionIndex,
+    /// functions from here on moved up by the two hooks
+    num_imported_functions: u32,
+}
+
+#[derive(Debug, Copy, Clone, loupe::MemoryUsage)]
+struct ShadowStack {
+    /// address of the first frame, set by the host
+    stack: GlobalIndex,
+    depth: GlobalIndex,
+}
+
+#[derive(Debug, Clone, loupe::MemoryUsage)]
+struct Counters {
+    /// address of the first counter, set by the host
+    base: GlobalIndex,
+    #[loupe(skip)]
+    map: Arc<CoverageMap>,
+}
 
 #[derive(Debug, loupe::MemoryUsage)]
 pub struct FunctionTransformer {
+    /// index of the function in the original module, as in its name section
     fn_id: u32,
+    module: Instrumented,
+    /// type of the block the body is wrapped in, so every exit falls through its end
+    #[loupe(skip)]
+    body: Option<TypeOrFuncType>,
+    /// the entry check was emitted
+    entered: bool,
+    /// blocks open within the function body
+    depth: u32,
+    /// counter of the next basic block
+    block: u32,
+}
+
+impl ModuleTransformer {
+    /// Calls the `__wassup.trace_enter` and `trace_exit` hooks around every function, see
+    /// [`CallTrace`](crate::CallTrace). Makes the guest a lot slower.
+    pub fn trace_calls(mut self, enabled: bool) -> Self {
+        self.trace_calls = enabled;
+        self
+    }
+
+    /// Keeps a stack of the running functions in a buffer from the guest's `__wassup_alloc`, for
+    /// the sampling [`Profile`](crate::Profile). Modules without the export get none.
+    pub fn shadow_stack(mut self, enabled: bool) -> Self {
+        self.shadow_stack = enabled;
+        self
+    }
+
+    /// Counts how often each basic block runs, for a [`Coverage`](crate::Coverage) report. The
+    /// counters are laid out by [`ModuleTransformer::prepare`] and allocated like the shadow
+    /// stack, modules compiled without it get none.
+    pub fn coverage(mut self, enabled: bool) -> Self {
+        self.coverage = enabled;
+        self
+    }
+
+    /// Lays out the coverage counters of `wasm`, which is compiled next. Functions are
+    /// instrumented in parallel, so the counters of each have to be known up front.
+    pub fn prepare(&self, wasm: &[u8]) {
+        if !self.coverage {
+            return;
+        }
+        let map = match CoverageMap::parse(wasm) {
+            Ok(map) => Some(Arc::new(map)),
+            Err(err) => {
+                log::warn!("failed to find the module's blocks for coverage: {}", err);
+                None
+            }
+        };
+        *self.coverage_map.lock().unwrap() = map;
+    }
 }
 
 impl Default for ModuleTransformer {
     fn default() -> Self {
-        Self {}
+        Self {
+            trace_calls: false,
+            shadow_stack: false,
+            coverage: false,
+            coverage_map: Mutex::new(None),
+            module: Mutex::new(None),
+        }
+    }
+}
+
+/// Part of the module cache key, so it lists what the instrumentation does and leaves out the
+/// state of the module being compiled
+impl fmt::Debug for ModuleTransformer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModuleTransformer")
+            .field("interrupt", &INTERRUPT_GLOBAL)
+            .field("fuel", &FUEL_GLOBAL)
+            .field("trace_calls", &self.trace_calls)
+            .field("shadow_stack", &self.shadow_stack)
+            .field("coverage", &self.coverage)
+            .finish()
     }
 }
 
 impl ModuleMiddleware for ModuleTransformer {
-    fn generate_function_middleware(
-        &self,
-        _lfi: LocalFunctionIndex,
-    ) -> Box<dyn FunctionMiddleware> {
-        Box::new(FunctionTransformer { fn_id: 0 })
+    fn generate_function_middleware(&self, lfi: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        let module = self
+            .module
+            .lock()
+            .unwrap()
+            .clone()
+            .expect("module info is transformed before its functions");
+        let block = match &module.coverage {
+            Some(counters) => counters.map.first_block(lfi.as_u32()),
+            None => 0,
+        };
+        Box::new(FunctionTransformer {
+            fn_id: module.num_imported_functions + lfi.as_u32(),
+            body: module.bodies.get(lfi.as_u32() as usize).copied().flatten(),
+            module,
+            entered: false,
+            depth: 0,
+            block,
+        })
+    }
+
+    fn transform_module_info(&self, info: &mut ModuleInfo) {
+        let num_imported_functions = info.num_imported_functions as u32;
+        let interrupt = info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        info.global_initializers.push(GlobalInit::I32Const(0));
+        info.exports
+            .insert(INTERRUPT_GLOBAL.to_string(), ExportIndex::Global(interrupt));
+        let fuel = info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        info.global_initializers.push(GlobalInit::I64Const(0));
+        info.exports
+            .insert(FUEL_GLOBAL.to_string(), ExportIndex::Global(fuel));
+
+        let trace = if self.trace_calls {
+            Some(add_trace_hooks(info))
+        } else {
+            None
+        };
+        let shadow_stack = if self.shadow_stack {
+            add_shadow_stack(info)
+        } else {
+            None
+        };
+        let coverage = match self.coverage_map.lock().unwrap().take() {
+            Some(map) if self.coverage => add_coverage(info, map),
+            _ if self.coverage => {
+                log::warn!("the module wasn't prepared, it gets no coverage counters");
+                None
+            }
+            _ => None,
+        };
+        let bodies = if trace.is_some() || shadow_stack.is_some() {
+            body_types(info)
+        } else {
+            vec![]
+        };
+        *self.module.lock().unwrap() = Some(Instrumented {
+            num_imported_functions,
+            interrupt,
+            fuel,
+            trace,
+            shadow_stack,
+            coverage,
+            bodies: Arc::new(bodies),
+        });
+    }
+}
+
+/// Imports the trace hooks. Imported functions come before the module's own ones, so every
+/// reference to those is moved up by two.
+fn add_trace_hooks(info: &mut ModuleInfo) -> TraceHooks {
+    let hook_type = FunctionType::new([Type::I32], []);
+    let signature = match info.signatures.iter().find(|(_, ty)| **ty == hook_type) {
+        Some((signature, _)) => signature,
+        None => info.signatures.push(hook_type),
+    };
+
+    let imported = info.num_imported_functions;
+    let hooks = TraceHooks {
+        enter: FunctionIndex::from_u32(imported as u32),
+        exit: FunctionIndex::from_u32(imported as u32 + 1),
+        num_imported_functions: imported as u32,
+    };
+    let mut functions = info.functions.values().copied().collect::<Vec<_>>();
+    functions.splice(imported..imported, [signature, signature]);
+    info.functions = functions.into_iter().collect();
+    info.num_imported_functions += 2;
+
+    for (field, index) in [(TRACE_ENTER, hooks.enter), (TRACE_EXIT, hooks.exit)] {
+        let key = (
+            HOOK_MODULE.to_string(),
+            field.to_string(),
+            info.imports.len() as u32,
+        );
+        info.imports.insert(key, ImportIndex::Function(index));
+    }
+
+    for export in info.exports.values_mut() {
+        if let ExportIndex::Function(index) = export {
+            *index = hooks.remap(*index);
+        }
+    }
+    if let Some(start) = &mut info.start_function {
+        *start = hooks.remap(*start);
+    }
+    for initializer in &mut info.table_initializers {
+        for index in initializer.elements.iter_mut() {
+            *index = hooks.remap(*index);
+        }
+    }
+    for elements in info.passive_elements.values_mut() {
+        for index in elements.iter_mut() {
+            *index = hooks.remap(*index);
+        }
+    }
+    for initializer in info.global_initializers.values_mut() {
+        if let GlobalInit::RefFunc(index) = initializer {
+            *index = hooks.remap(*index);
+        }
+    }
+    info.function_names = info
+        .function_names
+        .drain()
+        .map(|(index, name)| (hooks.remap(index), name))
+        .collect();
+
+    hooks
+}
+
+/// The block type of each of the module's own functions' results. Several results need a
+/// `[] -> results` type in the module, functions without one aren't wrapped.
+fn body_types(info: &ModuleInfo) -> Vec<Option<TypeOrFuncType>> {
+    let value_type = |ty: &Type| match ty {
+        Type::I32 => WpType::I32,
+        Type::I64 => WpType::I64,
+        Type::F32 => WpType::F32,
+        Type::F64 => WpType::F64,
+        Type::V128 => WpType::V128,
+        Type::ExternRef => WpType::ExternRef,
+        Type::FuncRef => WpType::FuncRef,
+    };
+    info.functions
+        .values()
+        .skip(info.num_imported_functions)
+        .map(|signature| match info.signatures[*signature].results() {
+            [] => Some(TypeOrFuncType::Type(WpType::EmptyBlockType)),
+            [ty] => Some(TypeOrFuncType::Type(value_type(ty))),
+            results => info
+                .signatures
+                .iter()
+                .find(|(_, ty)| ty.params().is_empty() && ty.results() == results)
+                .map(|(index, _)| TypeOrFuncType::FuncType(index.as_u32())),
+        })
+        .collect()
+}
+
+/// Exports the guest's memory for the host to read the instrumentation from
+fn export_memory(info: &mut ModuleInfo, purpose: &str) -> Option<()> {
+    if info.memories.is_empty() {
+        log::warn!("the module has no memory, it gets no {}", purpose);
+        return None;
+    }
+    info.exports.insert(
+        INSTRUMENTED_MEMORY.to_string(),
+        ExportIndex::Memory(MemoryIndex::from_u32(0)),
+    );
+    Some(())
+}
+
+fn add_shadow_stack(info: &mut ModuleInfo) -> Option<ShadowStack> {
+    export_memory(info, "shadow stack")?;
+    let stack = info
+        .globals
+        .push(GlobalType::new(Type::I32, Mutability::Var));
+    info.global_initializers.push(GlobalInit::I32Const(0));
+    let depth = info
+        .globals
+        .push(GlobalType::new(Type::I32, Mutability::Var));
+    info.global_initializers.push(GlobalInit::I32Const(0));
+    info.exports
+        .insert(SHADOW_STACK_GLOBAL.to_string(), ExportIndex::Global(stack));
+    info.exports
+        .insert(SHADOW_DEPTH_GLOBAL.to_string(), ExportIndex::Global(depth));
+    Some(ShadowStack { stack, depth })
+}
+
+fn add_coverage(info: &mut ModuleInfo, map: Arc<CoverageMap>) -> Option<Counters> {
+    export_memory(info, "coverage counters")?;
+    let base = info
+        .globals
+        .push(GlobalType::new(Type::I32, Mutability::Var));
+    info.global_initializers.push(GlobalInit::I32Const(0));
+    let len = info
+        .globals
+        .push(GlobalType::new(Type::I32, Mutability::Const));
+    info.global_initializers
+        .push(GlobalInit::I32Const(map.len() as i32));
+    info.exports
+        .insert(COVERAGE_GLOBAL.to_string(), ExportIndex::Global(base));
+    info.exports
+        .insert(COVERAGE_LEN_GLOBAL.to_string(), ExportIndex::Global(len));
+    Some(Counters { base, map })
+}
+
+/// Whether a basic block starts after `operator`, with `depth` blocks open within the function
+/// before it. [`CoverageMap`] finds the blocks the same way.
+pub(crate) fn starts_block_after(operator: &Operator<'_>, depth: u32) -> bool {
+    match operator {
+        Operator::Loop { .. } | Operator::If { .. } | Operator::Else | Operator::BrIf { .. } => {
+            true
+        }
+        // the function's own `end` is its last operator
+        Operator::End => depth > 0,
+        _ => false,
+    }
+}
+
+/// Blocks open within the function after `operator`, with `depth` open before it
+pub(crate) fn depth_after(operator: &Operator<'_>, depth: u32) -> u32 {
+    match operator {
+        Operator::Block { .. }
+        | Operator::Loop { .. }
+        | Operator::If { .. }
+        | Operator::Try { .. } => depth + 1,
+        Operator::End => depth.saturating_sub(1),
+        _ => depth,
+    }
+}
+
+impl TraceHooks {
+    fn remap(&self, index: FunctionIndex) -> FunctionIndex {
+        match index.as_u32() {
+            index if index >= self.num_imported_functions => FunctionIndex::from_u32(index + 2),
+            _ => index,
+        }
     }
 
-    fn transform_module_info(&self, _info: &mut ModuleInfo) {}
+    fn remap_u32(&self, index: u32) -> u32 {
+        self.remap(FunctionIndex::from_u32(index)).as_u32()
+    }
+}
+
+impl FunctionTransformer {
+    /// Burns a unit of fuel and traps if the host asks to
+    fn push_interrupt_check(&self, state: &mut MiddlewareReaderState<'_>) {
+        let fuel = self.module.fuel.as_u32();
+        state.extend(&[
+            Operator::GlobalGet { global_index: fuel },
+            Operator::I64Const { value: 1 },
+            Operator::I64Add,
+            Operator::GlobalSet { global_index: fuel },
+            Operator::GlobalGet {
+                global_index: self.module.interrupt.as_u32(),
+            },
+            Operator::If {
+                ty: TypeOrFuncType::Type(WpType::EmptyBlockType),
+            },
+            Operator::Unreachable,
+            Operator::End,
+        ]);
+    }
+
+    fn push_hook(&self, hook: FunctionIndex, state: &mut MiddlewareReaderState<'_>) {
+        state.extend(&[
+            Operator::I32Const {
+                value: self.fn_id as i32,
+            },
+            Operator::Call {
+                function_index: hook.as_u32(),
+            },
+        ]);
+    }
+
+    fn push_entry(&self, state: &mut MiddlewareReaderState<'_>) {
+        if let Some(hooks) = self.module.trace {
+            self.push_hook(hooks.enter, state);
+        }
+        if let Some(ShadowStack { stack, depth }) = self.module.shadow_stack {
+            let (stack, depth) = (stack.as_u32(), depth.as_u32());
+            state.extend(&[
+                // record the frame if the stack is allocated and has room for it
+                Operator::GlobalGet {
+                    global_index: depth,
+                },
+                Operator::I32Const {
+                    value: SHADOW_STACK_FRAMES as i32,
+                },
+                Operator::I32LtU,
+                Operator::GlobalGet {
+                    global_index: stack,
+                },
+                Operator::I32Const { value: 0 },
+                Operator::I32Ne,
+                Operator::I32And,
+                Operator::If {
+                    ty: TypeOrFuncType::Type(WpType::EmptyBlockType),
+                },
+                Operator::GlobalGet {
+                    global_index: stack,
+                },
+                Operator::GlobalGet {
+                    global_index: depth,
+                },
+                Operator::I32Const { value: 2 },
+                Operator::I32Shl,
+                Operator::I32Add,
+                Operator::I32Const {
+                    value: self.fn_id as i32,
+                },
+                Operator::I32Store {
+                    memarg: MemoryImmediate {
+                        align: 2,
+                        offset: 0,
+                        memory: 0,
+                    },
+                },
+                Operator::End,
+                Operator::GlobalGet {
+                    global_index: depth,
+                },
+                Operator::I32Const { value: 1 },
+                Operator::I32Add,
+                Operator::GlobalSet {
+                    global_index: depth,
+                },
+            ]);
+        }
+    }
+
+    fn push_counter(&mut self, state: &mut MiddlewareReaderState<'_>) {
+        let base = match &self.module.coverage {
+            Some(counters) => counters.base.as_u32(),
+            None => return,
+        };
+        let memarg = MemoryImmediate {
+            align: 2,
+            offset: self.block as u64 * 4,
+            memory: 0,
+        };
+        self.block += 1;
+        state.extend(&[
+            // the counters may not be allocated yet
+            Operator::GlobalGet { global_index: base },
+            Operator::If {
+                ty: TypeOrFuncType::Type(WpType::EmptyBlockType),
+            },
+            Operator::GlobalGet { global_index: base },
+            Operator::GlobalGet { global_index: base },
+            Operator::I32Load { memarg },
+            Operator::I32Const { value: 1 },
+            Operator::I32Add,
+            Operator::I32Store { memarg },
+            Operator::End,
+        ]);
+    }
+
+    fn push_exit(&self, state: &mut MiddlewareReaderState<'_>) {
+        if let Some(hooks) = self.module.trace {
+            self.push_hook(hooks.exit, state);
+        }
+        if let Some(ShadowStack { depth, .. }) = self.module.shadow_stack {
+            let depth = depth.as_u32();
+            state.extend(&[
+                Operator::GlobalGet {
+                    global_index: depth,
+                },
+                Operator::I32Const { value: 1 },
+                Operator::I32Sub,
+                Operator::GlobalSet {
+                    global_index: depth,
+                },
+            ]);
+        }
+    }
+
+    fn remap(&self, function_index: u32) -> u32 {
+        match self.module.trace {
+            Some(hooks) => hooks.remap_u32(function_index),
+            None => function_index,
+        }
+    }
+
+    /// Runs the exit instrumentation wherever the function returns and points calls at the moved
+    /// functions. Branches out of a wrapped body land before the exit at its end, a `br_if` or
+    /// `br_table` out of the rare unwrapped one leaves without it.
+    fn instrument<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Operator<'a> {
+        match operator {
+            Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::If { .. }
+            | Operator::Try { .. } => {
+                self.depth += 1;
+                operator
+            }
+            Operator::End if self.depth > 0 => {
+                self.depth -= 1;
+                operator
+            }
+            Operator::End if self.body.is_some() => {
+                state.push_operator(Operator::End);
+                self.push_exit(state);
+                operator
+            }
+            Operator::End | Operator::Return | Operator::ReturnCallIndirect { .. } => {
+                self.push_exit(state);
+                operator
+            }
+            Operator::Br { relative_depth }
+                if relative_depth == self.depth && self.body.is_none() =>
+            {
+                self.push_exit(state);
+                operator
+            }
+            Operator::Call { function_index } => Operator::Call {
+                function_index: self.remap(function_index),
+            },
+            Operator::ReturnCall { function_index } => {
+                self.push_exit(state);
+                Operator::ReturnCall {
+                    function_index: self.remap(function_index),
+                }
+            }
+            Operator::RefFunc { function_index } => Operator::RefFunc {
+                function_index: self.remap(function_index),
+            },
+            operator => operator,
+        }
+    }
 }
 
 impl FunctionMiddleware for FunctionTransformer {
@@ -33,22 +622,162 @@ impl FunctionMiddleware for FunctionTransformer {
         operator: Operator<'a>,
         state: &mut MiddlewareReaderState<'a>,
     ) -> Result<(), MiddlewareError> {
-        // match &operator {
-        //     Operator::Call { .. } => {}
-        //     Operator::CallIndirect { .. } => {}
-        //     Operator::ReturnCall { .. } => {}
-        //     Operator::ReturnCallIndirect { .. } => {}
-        //     Operator::LocalGet { .. } => {}
-        //     Operator::LocalSet { .. } => {}
-        //     Operator::LocalTee { .. } => {}
-        //     Operator::GlobalGet { .. } => {}
-        //     Operator::GlobalSet { .. } => {}
-        //     _ => (),
-        // }
-        // if let Operator::LocalGet { .. } = &operator {
-        //     state.push_operator(Operator::Call { function_index: 0 })
-        // }
+        if !self.entered {
+            self.entered = true;
+            self.push_interrupt_check(state);
+            self.push_entry(state);
+            if let Some(ty) = self.body {
+                state.push_operator(Operator::Block { ty });
+            }
+            self.push_counter(state);
+        }
+
+        let starts_block = starts_block_after(&operator, self.depth);
+        let operator = self.instrument(operator, state);
+        let is_loop = matches!(operator, Operator::Loop { .. });
         state.push_operator(operator);
+        if is_loop {
+            // every iteration branches back to the start of the loop
+            self.push_interrupt_check(state);
+        }
+        if starts_block {
+            self.push_counter(state);
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Backend, GuestInstance, InstanceBuilder};
+    use wasmer::{Module, Value};
+
+    /// A guest with a bump allocator starting at 1025, `outer` is function 2 and calls the others,
+    /// which branch out of their bodies
+    const GUEST: &str = r#"(module
+        (type (func (result i32 i32)))
+        (memory (export "memory") 1)
+        (global $heap (mut i32) (i32.const 1025))
+        (func (export "__wassup_alloc") (param $len i32) (result i32)
+            global.get $heap
+            global.get $heap
+            local.get $len
+            i32.add
+            global.set $heap)
+        (func (export "poll_runtime") (result i64)
+            i64.const 0)
+        (func (export "outer")
+            call $inner
+            call $value
+            drop
+            call $pair
+            drop
+            drop)
+        (func $inner
+            i32.const 1
+            br_if 0
+            unreachable)
+        (func $value (result i32)
+            i32.const 7
+            i32.const 0
+            br_table 0)
+        (func $pair (result i32 i32)
+            i32.const 1
+            i32.const 2
+            i32.const 1
+            br_if 0))"#;
+
+    /// A guest with the same allocator whose function 2 takes a few branches
+    const BRANCHY: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $heap (mut i32) (i32.const 1024))
+        (func (export "__wassup_alloc") (param $len i32) (result i32)
+            global.get $heap
+            global.get $heap
+            local.get $len
+            i32.add
+            global.set $heap)
+        (func (export "poll_runtime") (result i64)
+            i64.const 0)
+        (func (export "branchy") (param $n i32)
+            local.get $n
+            if
+                nop
+            else
+                nop
+            end
+            (loop $again
+                local.get $n
+                i32.const 1
+                i32.sub
+                local.tee $n
+                br_if $again)))"#;
+
+    fn instrumented(transformer: ModuleTransformer, wat: &str) -> GuestInstance {
+        let wasm = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        transformer.prepare(&wasm);
+        let store = Backend::default().make_store(Arc::new(transformer));
+        let module = Module::new(&store, wasm).unwrap();
+        InstanceBuilder::new(&module).build().unwrap()
+    }
+
+    fn global(instance: &GuestInstance, name: &str) -> i32 {
+        let global = instance.instance().exports.get_global(name).unwrap();
+        global.get().i32().unwrap()
+    }
+
+    #[test]
+    fn shadow_stack_is_allocated_by_the_guest() {
+        let instance = instrumented(ModuleTransformer::default().shadow_stack(true), GUEST);
+        let base = global(&instance, SHADOW_STACK_GLOBAL);
+        // aligned past the allocator's start
+        assert_eq!(base, 1028);
+
+        instance.call("outer").unwrap();
+        // every branch out of a function popped its frame
+        assert_eq!(global(&instance, SHADOW_DEPTH_GLOBAL), 0);
+        // `pair` was the last one called from `outer`
+        let memory = instance.instance().exports.get_memory(INSTRUMENTED_MEMORY).unwrap();
+        let view = memory.view::<u32>();
+        let frames = view[base as usize / 4..][..2].iter().map(|frame| frame.get());
+        assert_eq!(frames.collect::<Vec<_>>(), [2, 5]);
+    }
+
+    #[test]
+    fn shadow_stack_without_allocator() {
+        let wat = GUEST.replace(r#"(export "__wassup_alloc")"#, "");
+        let instance = instrumented(ModuleTransformer::default().shadow_stack(true), &wat);
+        assert_eq!(global(&instance, SHADOW_STACK_GLOBAL), 0);
+        instance.call("outer").unwrap();
+        let memory = instance.instance().exports.get_memory(INSTRUMENTED_MEMORY).unwrap();
+        assert!(memory.view::<u32>()[..4].iter().all(|cell| cell.get() == 0));
+    }
+
+    #[test]
+    fn coverage_counters_match_the_map() {
+        let instance = instrumented(ModuleTransformer::default().coverage(true), BRANCHY);
+        let wasm = wasmer::wat2wasm(BRANCHY.as_bytes()).unwrap();
+        let map = Arc::new(CoverageMap::parse(&wasm).unwrap());
+        assert_eq!(global(&instance, COVERAGE_LEN_GLOBAL), map.len() as i32);
+        assert_eq!(global(&instance, COVERAGE_GLOBAL), 1024);
+        assert_eq!(map.first_block(2), 2);
+
+        let branchy = instance.instance().exports.get_native_function::<i32, ()>("branchy");
+        branchy.unwrap().call(3).unwrap();
+        let coverage = instance.coverage(&map).unwrap();
+        // `__wassup_alloc` ran before the counters were there and `poll_runtime` not at all, then
+        // the entry, then, else, after the `if`, loop body, after the `br_if` and after the loop
+        // blocks of `branchy`
+        assert_eq!(coverage.counts(), [0, 0, 1, 1, 0, 1, 3, 1, 1]);
+    }
+
+    #[test]
+    fn fuel_counts_entries_and_iterations() {
+        let instance = instrumented(ModuleTransformer::default(), BRANCHY);
+        let branchy = instance.instance().exports.get_native_function::<i32, ()>("branchy");
+        branchy.unwrap().call(3).unwrap();
+        let fuel = instance.instance().exports.get_global(FUEL_GLOBAL).unwrap();
+        assert_eq!(fuel.get(), Value::I64(4));
+    }
+}
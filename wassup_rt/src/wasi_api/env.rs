@@ -1,5 +1,6 @@
+use std::any::Any;
 use std::sync::Arc;
-use wasmer::{LazyInit, Memory, WasmerEnv};
+use wasmer::{Array, LazyInit, Memory, WasmPtr, WasmerEnv};
 use crate::wasi_api::state::State;
 
 #[derive(Clone, WasmerEnv)]
@@ -14,4 +15,31 @@ impl WasiEnv {
     pub fn memory(&self) -> &Memory {
         self.memory_ref().unwrap()
     }
-}
\ No newline at end of file
+
+    /// The embedder's data set with [`InstanceBuilder::data`](crate::InstanceBuilder::data)
+    pub fn data<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.state.data.as_ref()?.downcast_ref()
+    }
+
+    /// Copies `len` bytes out of guest memory, `None` if they are out of bounds
+    pub fn read_bytes(&self, ptr: WasmPtr<u8, Array>, len: u32) -> Option<Vec<u8>> {
+        let cells = ptr.deref(self.memory(), 0, len)?;
+        Some(cells.iter().map(|cell| cell.get()).collect())
+    }
+
+    /// Reads a guest string, `None` if it is out of bounds or not utf-8
+    pub fn read_str(&self, ptr: WasmPtr<u8, Array>, len: u32) -> Option<String> {
+        String::from_utf8(self.read_bytes(ptr, len)?).ok()
+    }
+
+    /// Copies `bytes` into guest memory, returns `false` if they don't fit
+    pub fn write_bytes(&self, ptr: WasmPtr<u8, Array>, bytes: &[u8]) -> bool {
+        match ptr.deref(self.memory(), 0, bytes.len() as u32) {
+            Some(cells) => {
+                cells.iter().zip(bytes).for_each(|(cell, byte)| cell.set(*byte));
+                true
+            }
+            None => false,
+        }
+    }
+}
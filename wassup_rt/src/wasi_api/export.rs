@@ -0,0 +1,18 @@
+use bytes::Bytes;
+use wasmer::{Array, WasmPtr};
+use crate::WasiEnv;
+
+/// Result of a call to a guest export, `status` 0 means `result` is the encoded output and
+/// anything else that it is an error message
+pub struct Completion {
+    pub status: u32,
+    pub result: Bytes,
+}
+
+pub fn export_complete(env: &WasiEnv, call_id: u64, status: u32, result: WasmPtr<u8, Array>, result_len: u32) {
+    let result = env.read_bytes(result, result_len).unwrap_or_default();
+    env.state.completions.insert(call_id, Completion {
+        status,
+        result: result.into(),
+    });
+}
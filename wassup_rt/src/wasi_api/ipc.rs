@@ -1,42 +1,264 @@
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use bytes::Bytes;
-use crossbeam_queue::{ArrayQueue};
-use wasi::Errno;
+use crossbeam_queue::ArrayQueue;
+use wasmer::Memory;
+use crate::metrics::Metrics;
+use crate::wasi_api::ring::{Broken, Ring};
 
+/// Messages buffered per direction before senders get `ERRNO_AGAIN`, unless the guest picks a
+/// capacity
+pub const DEFAULT_CAPACITY: u32 = 128;
+/// Keeps a guest from making the host allocate huge queues
+pub const MAX_CAPACITY: u32 = 1 << 16;
+
+/// The host end of a channel opened by the guest, cloning it gives another handle to the same
+/// channel
+#[derive(Clone)]
 pub struct Ipc(Arc<InnerIpc>);
+
 struct InnerIpc {
     id: u32,
+    /// guest -> host
     recv_buff: ArrayQueue<Bytes>,
+    /// host -> guest, delivered between polls
+    send_buff: ArrayQueue<Bytes>,
+    /// the message handed to `ipc_notify`, picked up by `ipc_recv_msg`
+    in_flight: Mutex<Option<Bytes>>,
+    rings: Mutex<Option<Rings>>,
+    /// taken from `send_buff` but didn't fit into the `rx` ring or the guest's buffer yet
+    stalled: Mutex<Option<Bytes>>,
+    /// a guest send failed on a full `recv_buff`, it gets `ipc_writable` once there is room
+    guest_blocked: AtomicBool,
+    /// either side dropped the channel
+    closed: AtomicBool,
+    /// the guest got `ipc_closed` for a close by the host
+    close_delivered: AtomicBool,
+    /// of the instance the channel belongs to
+    metrics: Arc<Metrics>,
 }
 
-pub mod syscalls {
-    use std::sync::Arc;
-    use std::sync::atomic::Ordering;
-    use crossbeam_queue::ArrayQueue;
-    use wasi::{Errno, ERRNO_INVAL, ERRNO_NOBUFS, ERRNO_NODEV, ERRNO_SUCCESS};
-    use wasmer_types::ValueType;
-    use crate::wasi_api::ipc::{InnerIpc, Ipc};
-    use crate::WasiEnv;
+pub enum TrySendError {
+    /// The guest hasn't taken enough messages yet, holds the message that couldn't be sent
+    Full(Bytes),
+    /// The channel is closed, holds the message that couldn't be sent
+    Closed(Bytes),
+}
+
+#[derive(Copy, Clone)]
+struct Rings {
+    /// guest -> host
+    tx: Ring,
+    /// host -> guest
+    rx: Ring,
+}
+
+/// How a message queued for the guest gets there
+pub(crate) enum Delivery {
+    /// hand it over with `ipc_notify(id, len)`
+    Notify(Bytes),
+    /// it was written into the `rx` ring, which was empty before, wake the guest with
+    /// `ipc_notify(id, 0)`
+    RingWasEmpty,
+    /// it was written into the `rx` ring
+    Ring,
+}
+
+impl Ipc {
+    fn new(id: u32, capacity: u32, metrics: Arc<Metrics>) -> Self {
+        Self(Arc::new(InnerIpc {
+            id,
+            recv_buff: ArrayQueue::new(capacity as usize),
+            send_buff: ArrayQueue::new(capacity as usize),
+            in_flight: Mutex::new(None),
+            rings: Mutex::new(None),
+            stalled: Mutex::new(None),
+            guest_blocked: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            close_delivered: AtomicBool::new(false),
+            metrics,
+        }))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0.id
+    }
+
+    /// Messages buffered per direction
+    pub fn capacity(&self) -> usize {
+        self.0.recv_buff.capacity()
+    }
+
+    /// Queues a message for the guest, it is delivered on the next poll
+    pub fn try_send(&self, msg: Bytes) -> Result<(), TrySendError> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(msg));
+        }
+        let len = msg.len();
+        self.0.send_buff.push(msg).map_err(TrySendError::Full)?;
+        self.0.metrics.message_to_guest(len);
+        Ok(())
+    }
+
+    /// Takes the next message the guest sent, messages sent before the channel was closed can
+    /// still be taken
+    pub fn try_recv(&self) -> Option<Bytes> {
+        self.0.recv_buff.pop()
+    }
+
+    /// Closes the channel for both sides. The guest still gets the messages queued before, then
+    /// its `recv` returns `None` and its sends fail.
+    pub fn close(&self) {
+        self.0.closed.store(true, Ordering::Release);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Ordering::Acquire)
+    }
+
+    /// Whether the guest should get `ipc_closed` now, once everything queued before the close
+    /// was delivered
+    pub(crate) fn take_close(&self) -> bool {
+        self.is_closed()
+            && self.0.send_buff.is_empty()
+            && self.0.stalled.lock().unwrap().is_none()
+            && !self.0.close_delivered.swap(true, Ordering::AcqRel)
+    }
+
+    /// Moves the next message queued for the guest into the `rx` ring, or marks it as in flight
+    /// if it has to go through `ipc_notify`. `None` if there is nothing to deliver right now.
+    pub(crate) fn next_delivery(&self, memory: &Memory) -> Option<Delivery> {
+        let mut stalled = self.0.stalled.lock().unwrap();
+        let msg = stalled.take().or_else(|| self.0.send_buff.pop())?;
+
+        let rx = match *self.0.rings.lock().unwrap() {
+            Some(rings) => rings.rx,
+            None => {
+                *self.0.in_flight.lock().unwrap() = Some(msg.clone());
+                return Some(Delivery::Notify(msg));
+            }
+        };
+
+        match rx.is_empty(memory) {
+            Ok(was_empty) if rx.fits(msg.len()) => match rx.push(memory, &msg) {
+                Ok(true) if was_empty => return Some(Delivery::RingWasEmpty),
+                Ok(true) => return Some(Delivery::Ring),
+                Ok(false) => {}
+                Err(Broken) => self.broken(),
+            },
+            Ok(true) => {
+                // too large for the ring, it may only overtake it once the guest emptied it
+                *self.0.in_flight.lock().unwrap() = Some(msg.clone());
+                return Some(Delivery::Notify(msg));
+            }
+            Ok(false) => {}
+            // the message goes through `ipc_notify` on the next poll, like the ones after it
+            Err(Broken) => self.broken(),
+        }
+
+        *stalled = Some(msg);
+        None
+    }
+
+    /// The guest's buffer was full when the message in flight was handed to `ipc_notify`, it is
+    /// delivered again on a later poll
+    pub(crate) fn redeliver(&self) {
+        if let Some(msg) = self.0.in_flight.lock().unwrap().take() {
+            *self.0.stalled.lock().unwrap() = Some(msg);
+        }
+    }
+
+    /// The guest corrupted a ring, closes the channel instead of trusting it any further
+    fn broken(&self) {
+        log::warn!("channel {} has a corrupted ring, closing it", self.0.id);
+        *self.0.rings.lock().unwrap() = None;
+        self.close();
+    }
+
+    /// Whether a blocked guest sender should be told that there is room again
+    pub(crate) fn take_writable(&self) -> bool {
+        !self.0.recv_buff.is_full() && self.0.guest_blocked.swap(false, Ordering::AcqRel)
+    }
+
+    /// Moves messages out of the `tx` ring, returns `false` if `recv_buff` filled up first
+    pub(crate) fn drain_ring(&self, memory: &Memory) -> bool {
+        let tx = match *self.0.rings.lock().unwrap() {
+            Some(rings) => rings.tx,
+            None => return true,
+        };
+
+        while !self.0.recv_buff.is_full() {
+            match tx.pop(memory) {
+                Ok(Some(msg)) => {
+                    self.0.metrics.message_from_guest(msg.len());
+                    self.0.recv_buff.push(msg).unwrap();
+                }
+                Ok(None) => return true,
+                Err(Broken) => {
+                    self.broken();
+                    return true;
+                }
+            }
+        }
+        match tx.is_empty(memory) {
+            Ok(is_empty) => is_empty,
+            Err(Broken) => {
+                self.broken();
+                true
+            }
+        }
+    }
+}
+
+impl TrySendError {
+    pub fn into_inner(self) -> Bytes {
+        match self {
+            TrySendError::Full(msg) | TrySendError::Closed(msg) => msg,
+        }
+    }
+}
+
+impl Debug for TrySendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
 
-    #[repr(C)]
-    #[derive(Copy, Clone, Debug)]
-    pub struct IpcMakeChannelResult {
-        id: u32,
-        err: Errno,
+impl Display for TrySendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("channel is full"),
+            TrySendError::Closed(_) => f.write_str("channel is closed"),
+        }
     }
+}
 
-    unsafe impl ValueType for IpcMakeChannelResult {}
+impl Error for TrySendError {}
 
-    pub fn ipc_make_channel(env: &WasiEnv) -> IpcMakeChannelResult {
+pub mod syscalls {
+    use std::sync::atomic::Ordering;
+    use wasi::{Errno, ERRNO_AGAIN, ERRNO_BADF, ERRNO_INVAL, ERRNO_NOBUFS, ERRNO_PIPE, ERRNO_SUCCESS};
+    use wasmer::{Array, WasmPtr};
+    use crate::wasi_api::ipc::{Ipc, Rings, DEFAULT_CAPACITY, MAX_CAPACITY};
+    use crate::wasi_api::ring::Ring;
+    use crate::WasiEnv;
+
+    pub fn ipc_make_channel(env: &WasiEnv, capacity: u32, id_out: WasmPtr<u32>) -> Errno {
         let state = &*env.state;
 
-        if state.ipcs.len() >= 128 {
-            return IpcMakeChannelResult {
-                id: 0,
-                err: ERRNO_NOBUFS,
-            };
+        if state.ipcs.len() >= state.max_ipcs {
+            return ERRNO_NOBUFS;
         }
+        let capacity = match capacity {
+            0 => DEFAULT_CAPACITY,
+            1..=MAX_CAPACITY => capacity,
+            _ => return ERRNO_INVAL,
+        };
 
         // This is technically a race condition, but I find it incredibly unlikely that for it to occur
         let mut step = 1;
@@ -46,40 +268,137 @@ pub mod syscalls {
                 break next_id;
             }
             // make steps larger each time to increase chance of hitting a free one
-            step = if let Ok(n) = step.checked_add(step) {
+            step = if let Some(n) = step.checked_add(step) {
                 n
             } else {
-                return IpcMakeChannelResult {
-                    id: 0,
-                    err: ERRNO_NOBUFS,
-                };
+                return ERRNO_NOBUFS;
             };
         };
-        let mut ipc = Ipc(Arc::new(InnerIpc {
-            id,
-            recv_buff: ArrayQueue::new(128),
-        }));
-        state.ipcs.insert(id, ipc);
-        
-        IpcMakeChannelResult {
-            id,
-            err: 0,
-        }
+
+        let cell = match id_out.deref(env.memory()) {
+            Some(cell) => cell,
+            None => return ERRNO_INVAL,
+        };
+        cell.set(id);
+
+        let ipc = Ipc::new(id, capacity, state.metrics.clone());
+        state.ipcs.insert(id, ipc.clone());
+        state.new_ipcs.push(ipc);
+
+        ERRNO_SUCCESS
     }
 
     pub fn ipc_drop_channel(env: &WasiEnv, id: u32) -> Errno {
-        if env.state.ipcs.remove(&id).is_some() {
+        match env.state.ipcs.remove(&id) {
+            Some((_, ipc)) => {
+                // host handles outlive this, they must not touch the guest's rings anymore
+                *ipc.0.rings.lock().unwrap() = None;
+                ipc.close();
+                ERRNO_SUCCESS
+            }
+            None => ERRNO_INVAL,
+        }
+    }
+
+    pub fn ipc_send_msg(env: &WasiEnv, id: u32, buffer: WasmPtr<u8, Array>, len_buf: u32) -> Errno {
+        let ipc = match env.state.ipcs.get(&id) {
+            Some(ipc) => ipc.clone(),
+            None => return ERRNO_BADF,
+        };
+        if ipc.is_closed() {
+            return ERRNO_PIPE;
+        }
+        let msg = match env.read_bytes(buffer, len_buf) {
+            Some(msg) => msg,
+            None => return ERRNO_INVAL,
+        };
+
+        let len = msg.len();
+        match ipc.0.recv_buff.push(msg.into()) {
+            Ok(()) => {
+                ipc.0.metrics.message_from_guest(len);
+                ERRNO_SUCCESS
+            }
+            Err(_) => {
+                ipc.0.guest_blocked.store(true, Ordering::Release);
+                ERRNO_AGAIN
+            }
+        }
+    }
+
+    pub fn ipc_recv_msg(env: &WasiEnv, id: u32, buffer: WasmPtr<u8, Array>, len_buf: u32) -> Errno {
+        let ipc = match env.state.ipcs.get(&id) {
+            Some(ipc) => ipc.clone(),
+            None => return ERRNO_BADF,
+        };
+        let msg = match ipc.0.in_flight.lock().unwrap().take() {
+            Some(msg) => msg,
+            None => return ERRNO_AGAIN,
+        };
+        if msg.len() as u32 > len_buf {
+            return ERRNO_INVAL;
+        }
+
+        if env.write_bytes(buffer, &msg) {
             ERRNO_SUCCESS
         } else {
             ERRNO_INVAL
         }
     }
 
-    pub fn ipc_send_msg(buffer: *const u8, len_buf: usize) -> Errno {
-        todo!()
+    pub fn ipc_attach_rings(env: &WasiEnv, id: u32, tx: u32, rx: u32) -> Errno {
+        let ipc = match env.state.ipcs.get(&id) {
+            Some(ipc) => ipc.clone(),
+            None => return ERRNO_BADF,
+        };
+        let memory = env.memory();
+        let rings = match (Ring::new(memory, tx), Ring::new(memory, rx)) {
+            (Some(tx), Some(rx)) => Rings { tx, rx },
+            _ => return ERRNO_INVAL,
+        };
+
+        *ipc.0.rings.lock().unwrap() = Some(rings);
+        ERRNO_SUCCESS
     }
 
-    pub fn ipc_recv_msg(buffer: *mut u8, len_buf: usize) -> Errno {
-        todo!()
+    pub fn ipc_ring_flush(env: &WasiEnv, id: u32) -> Errno {
+        let ipc = match env.state.ipcs.get(&id) {
+            Some(ipc) => ipc.clone(),
+            None => return ERRNO_BADF,
+        };
+        if ipc.is_closed() {
+            return ERRNO_PIPE;
+        }
+
+        if ipc.drain_ring(env.memory()) {
+            ERRNO_SUCCESS
+        } else {
+            ipc.0.guest_blocked.store(true, Ordering::Release);
+            ERRNO_AGAIN
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmer::{MemoryType, Store};
+    use wasmer_engine_universal::Universal;
+
+    #[test]
+    fn redelivered_message_comes_first() {
+        let store = Store::new(&Universal::headless().engine());
+        let memory = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
+        let ipc = Ipc::new(1, 2, Arc::default());
+        ipc.try_send(Bytes::from_static(b"first")).unwrap();
+        ipc.try_send(Bytes::from_static(b"second")).unwrap();
+
+        // the guest's buffer was full
+        assert!(matches!(ipc.next_delivery(&memory), Some(Delivery::Notify(msg)) if msg == "first"));
+        ipc.redeliver();
+        assert!(ipc.0.in_flight.lock().unwrap().is_none());
+
+        assert!(matches!(ipc.next_delivery(&memory), Some(Delivery::Notify(msg)) if msg == "first"));
+        assert!(matches!(ipc.next_delivery(&memory), Some(Delivery::Notify(msg)) if msg == "second"));
     }
 }
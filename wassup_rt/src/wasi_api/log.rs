@@ -0,0 +1,178 @@
+use crate::wasi_api::env::WasiEnv;
+use log::{Level, Metadata, Record};
+use std::fmt::Write;
+use wasmer::{Array, Memory, WasmPtr};
+use wasmer_types::ValueType;
+
+/// Spans shown in front of an event at most, the parents come from the guest and may form a cycle
+const MAX_SPAN_DEPTH: usize = 32;
+
+/// Guest side span, kept to prefix events with their span context
+pub struct GuestSpan {
+    name: String,
+    fields: String,
+    parent: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct LogRecord {
+    level: u32,
+    span: u64,
+    parent: u64,
+    target: WasmPtr<u8, Array>,
+    target_len: u32,
+    name: WasmPtr<u8, Array>,
+    name_len: u32,
+    fields: WasmPtr<u8, Array>,
+    fields_len: u32,
+    file: WasmPtr<u8, Array>,
+    file_len: u32,
+    line: u32,
+}
+
+unsafe impl ValueType for LogRecord {}
+
+fn level(level: u32) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+fn read_string(memory: &Memory, ptr: WasmPtr<u8, Array>, len: u32) -> String {
+    ptr.get_utf8_string(memory, len).unwrap_or_default()
+}
+
+/// Decodes fields written by the guest's `FieldEncoder` into `key=value` pairs, `message` is
+/// returned separately
+fn decode_fields(memory: &Memory, ptr: WasmPtr<u8, Array>, len: u32) -> (String, String) {
+    let bytes = match ptr.deref(memory, 0, len) {
+        Some(cells) => cells.iter().map(|cell| cell.get()).collect::<Vec<u8>>(),
+        None => return Default::default(),
+    };
+
+    let mut message = String::new();
+    let mut fields = String::new();
+    let mut rest = &bytes[..];
+    while let (Some(key), Some(value)) = (next_field(&mut rest), next_field(&mut rest)) {
+        if key == "message" {
+            message = value;
+        } else {
+            if !fields.is_empty() {
+                fields.push(' ');
+            }
+            let _ = write!(fields, "{}={}", key, value);
+        }
+    }
+
+    (message, fields)
+}
+
+fn next_field(rest: &mut &[u8]) -> Option<String> {
+    let len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    let value = String::from_utf8_lossy(rest.get(4..4 + len)?).into_owned();
+    *rest = &rest[4 + len..];
+    Some(value)
+}
+
+/// Renders `outer{a=1}:inner{b=2}` for the span and its parents, the outermost ones are cut off
+/// past `MAX_SPAN_DEPTH`
+fn span_context(env: &WasiEnv, mut span: u64) -> String {
+    let mut spans = vec![];
+    while spans.len() < MAX_SPAN_DEPTH {
+        let guest_span = match env.state.spans.get(&span) {
+            Some(guest_span) => guest_span,
+            None => break,
+        };
+        if guest_span.fields.is_empty() {
+            spans.push(guest_span.name.clone());
+        } else {
+            spans.push(format!("{}{{{}}}", guest_span.name, guest_span.fields));
+        }
+        span = guest_span.parent;
+    }
+    spans.reverse();
+    spans.join(":")
+}
+
+pub fn log_max_level(_env: &WasiEnv) -> u32 {
+    log::max_level() as u32
+}
+
+pub fn log_event(env: &WasiEnv, record: WasmPtr<LogRecord>) {
+    let memory = env.memory();
+    let record = match record.deref(memory) {
+        Some(cell) => cell.get(),
+        None => return,
+    };
+
+    let target = read_string(memory, record.target, record.target_len);
+    let level = level(record.level);
+    let logger = log::logger();
+    if !logger.enabled(&Metadata::builder().level(level).target(&target).build()) {
+        return;
+    }
+
+    let (message, fields) = decode_fields(memory, record.fields, record.fields_len);
+    let file = read_string(memory, record.file, record.file_len);
+    let context = span_context(env, record.span);
+
+    let mut line = format!("[instance {}] ", env.state.instance_id);
+    if !context.is_empty() {
+        let _ = write!(line, "{}: ", context);
+    }
+    line.push_str(&message);
+    if !fields.is_empty() {
+        let _ = write!(line, " {}", fields);
+    }
+
+    logger.log(
+        &Record::builder()
+            .level(level)
+            .target(&target)
+            .file((!file.is_empty()).then_some(file.as_str()))
+            .line((record.line != 0).then_some(record.line))
+            .args(format_args!("{}", line))
+            .build(),
+    );
+}
+
+pub fn log_span_new(env: &WasiEnv, record: WasmPtr<LogRecord>) {
+    let memory = env.memory();
+    let record = match record.deref(memory) {
+        Some(cell) => cell.get(),
+        None => return,
+    };
+
+    if record.parent == record.span {
+        log::warn!("[instance {}] span {} is its own parent", env.state.instance_id, record.span);
+        return;
+    }
+    let (_, fields) = decode_fields(memory, record.fields, record.fields_len);
+    env.state.spans.insert(
+        record.span,
+        GuestSpan {
+            name: read_string(memory, record.name, record.name_len),
+            fields,
+            parent: record.parent,
+        },
+    );
+}
+
+pub fn log_span_record(env: &WasiEnv, id: u64, fields: WasmPtr<u8, Array>, fields_len: u32) {
+    let (_, fields) = decode_fields(env.memory(), fields, fields_len);
+    if let Some(mut span) = env.state.spans.get_mut(&id) {
+        if !span.fields.is_empty() && !fields.is_empty() {
+            span.fields.push(' ');
+        }
+        span.fields.push_str(&fields);
+    }
+}
+
+pub fn log_span_close(env: &WasiEnv, id: u64) {
+    env.state.spans.remove(&id);
+}
@@ -0,0 +1,24 @@
+use crate::wasi_api::env::WasiEnv;
+use wasmer::WasmPtr;
+use wasmer_types::ValueType;
+
+/// The guest runtime's numbers, reported after every poll
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RuntimeMetrics {
+    tasks: u32,
+    timers: u32,
+    /// tasks woken but not polled yet
+    poll_queue: u32,
+}
+
+unsafe impl ValueType for RuntimeMetrics {}
+
+pub fn report_metrics(env: &WasiEnv, record: WasmPtr<RuntimeMetrics>) {
+    if let Some(cell) = record.deref(env.memory()) {
+        let record = cell.get();
+        env.state
+            .metrics
+            .set_runtime(record.tasks, record.timers, record.poll_queue);
+    }
+}
@@ -1,13 +1,25 @@
+use crate::transformer::{HOOK_MODULE, TRACE_ENTER, TRACE_EXIT};
 use wasmer::Function;
 use wasmer::{imports, ImportObject, Store};
 
 mod env;
+mod export;
 mod syscalls;
 mod unix;
 mod state;
 mod ipc;
+mod log;
+mod metrics;
+mod panic;
+mod ring;
+mod tasks;
+mod trace;
 
 pub use env::WasiEnv;
+pub use ipc::{Ipc, TrySendError};
+pub use panic::{GuestPanic, Panicked};
+pub use tasks::{GuestTask, TaskState};
+pub(crate) use ipc::Delivery;
 pub use state::State;
 
 pub fn generate_imports(store: &Store, env: WasiEnv) -> ImportObject {
@@ -23,6 +35,27 @@ pub fn generate_imports(store: &Store, env: WasiEnv) -> ImportObject {
             "environ_get" => Function::new_native_with_env(store, env.clone(), syscalls::environ_get),
             "environ_sizes_get" => Function::new_native_with_env(store, env.clone(), syscalls::environ_sizes_get),
             "proc_exit" => Function::new_native_with_env(store, env.clone(), syscalls::proc_exit),
+        },
+        "env" => {
+            "ipc_make_channel" => Function::new_native_with_env(store, env.clone(), ipc::syscalls::ipc_make_channel),
+            "ipc_drop_channel" => Function::new_native_with_env(store, env.clone(), ipc::syscalls::ipc_drop_channel),
+            "ipc_send_msg" => Function::new_native_with_env(store, env.clone(), ipc::syscalls::ipc_send_msg),
+            "ipc_recv_msg" => Function::new_native_with_env(store, env.clone(), ipc::syscalls::ipc_recv_msg),
+            "ipc_attach_rings" => Function::new_native_with_env(store, env.clone(), ipc::syscalls::ipc_attach_rings),
+            "ipc_ring_flush" => Function::new_native_with_env(store, env.clone(), ipc::syscalls::ipc_ring_flush),
+            "export_complete" => Function::new_native_with_env(store, env.clone(), export::export_complete),
+            "report_panic" => Function::new_native_with_env(store, env.clone(), panic::report_panic),
+            "report_metrics" => Function::new_native_with_env(store, env.clone(), metrics::report_metrics),
+            "report_tasks" => Function::new_native_with_env(store, env.clone(), tasks::report_tasks),
+            "log_max_level" => Function::new_native_with_env(store, env.clone(), log::log_max_level),
+            "log_event" => Function::new_native_with_env(store, env.clone(), log::log_event),
+            "log_span_new" => Function::new_native_with_env(store, env.clone(), log::log_span_new),
+            "log_span_record" => Function::new_native_with_env(store, env.clone(), log::log_span_record),
+            "log_span_close" => Function::new_native_with_env(store, env.clone(), log::log_span_close),
+        },
+        HOOK_MODULE => {
+            TRACE_ENTER => Function::new_native_with_env(store, env.clone(), trace::trace_enter),
+            TRACE_EXIT => Function::new_native_with_env(store, env.clone(), trace::trace_exit),
         }
     }
 }
@@ -0,0 +1,85 @@
+use crate::wasi_api::env::WasiEnv;
+use std::error::Error;
+use std::fmt::{self, Display};
+use wasmer::{Array, RuntimeError, WasmPtr};
+use wasmer_types::ValueType;
+
+/// What the guest's panic hook reported before the guest aborted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestPanic {
+    pub message: String,
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+    /// The guest task that was being polled
+    pub task: Option<u64>,
+}
+
+/// The trap a guest panic ended in, along with what the panic hook reported
+#[derive(Debug, Clone)]
+pub struct Panicked {
+    pub panic: GuestPanic,
+    pub trap: RuntimeError,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PanicRecord {
+    message: WasmPtr<u8, Array>,
+    message_len: u32,
+    file: WasmPtr<u8, Array>,
+    file_len: u32,
+    line: u32,
+    column: u32,
+    /// `u64::MAX` outside of a task
+    task: u64,
+}
+
+unsafe impl ValueType for PanicRecord {}
+
+pub fn report_panic(env: &WasiEnv, record: WasmPtr<PanicRecord>) {
+    let record = match record.deref(env.memory()) {
+        Some(cell) => cell.get(),
+        None => return,
+    };
+    let read = |ptr: WasmPtr<u8, Array>, len| {
+        env.read_bytes(ptr, len)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default()
+    };
+    let panic = GuestPanic {
+        message: read(record.message, record.message_len),
+        file: read(record.file, record.file_len),
+        line: record.line,
+        column: record.column,
+        task: (record.task != u64::MAX).then_some(record.task),
+    };
+
+    log::debug!("[instance {}] guest {}", env.state.instance_id, panic);
+    *env.state.panic.lock().unwrap() = Some(panic);
+}
+
+impl Display for GuestPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "panicked at '{}'", self.message)?;
+        if !self.file.is_empty() {
+            write!(f, ", {}:{}:{}", self.file, self.line, self.column)?;
+        }
+        if let Some(task) = self.task {
+            write!(f, " in task {}", task)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "guest {}", self.panic)
+    }
+}
+
+impl Error for Panicked {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.trap)
+    }
+}
@@ -0,0 +1,245 @@
+use bytes::Bytes;
+use wasmer::{Array, Memory, WasmCell, WasmPtr};
+
+/// Bytes in front of every message holding its length
+const FRAME_HEADER: u32 = 4;
+
+/// A ring buffer the guest shares through `ipc_attach_rings`, see `RingHeader` in `wassup_std` for
+/// the layout. Only touched while the guest isn't running.
+#[derive(Copy, Clone)]
+pub struct Ring {
+    /// offset of the `RingHeader` in guest memory
+    header: u32,
+    /// as attached, the guest may overwrite them in its header later
+    capacity: u32,
+    data: u32,
+}
+
+/// The guest corrupted the positions in a ring's header, its channel can't be used anymore
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Broken;
+
+impl Ring {
+    /// Checks the header and that the data lies in guest memory
+    pub fn new(memory: &Memory, header: u32) -> Option<Self> {
+        let cells = WasmPtr::<u32, Array>::new(header).deref(memory, 0, 4)?;
+        let (capacity, data) = (cells[2].get(), cells[3].get());
+        if !capacity.is_power_of_two() || capacity < FRAME_HEADER {
+            return None;
+        }
+        WasmPtr::<u8, Array>::new(data).deref(memory, 0, capacity)?;
+
+        Some(Self {
+            header,
+            capacity,
+            data,
+        })
+    }
+
+    /// `head` and `tail`, at most `capacity` apart
+    fn positions(&self, memory: &Memory) -> Result<(u32, u32), Broken> {
+        let cells = WasmPtr::<u32, Array>::new(self.header)
+            .deref(memory, 0, 2)
+            .ok_or(Broken)?;
+        let (head, tail) = (cells[0].get(), cells[1].get());
+        if tail.wrapping_sub(head) > self.capacity {
+            return Err(Broken);
+        }
+        Ok((head, tail))
+    }
+
+    fn set(&self, memory: &Memory, field: u32, value: u32) -> Result<(), Broken> {
+        let cell = WasmPtr::<u32>::new(self.header + field * 4).deref(memory);
+        cell.ok_or(Broken)?.set(value);
+        Ok(())
+    }
+
+    fn data<'a>(&self, memory: &'a Memory) -> Result<Vec<WasmCell<'a, u8>>, Broken> {
+        WasmPtr::<u8, Array>::new(self.data)
+            .deref(memory, 0, self.capacity)
+            .ok_or(Broken)
+    }
+
+    /// Whether a message of `len` bytes fits into the empty ring
+    pub fn fits(&self, len: usize) -> bool {
+        len as u64 + FRAME_HEADER as u64 <= self.capacity as u64
+    }
+
+    pub fn is_empty(&self, memory: &Memory) -> Result<bool, Broken> {
+        let (head, tail) = self.positions(memory)?;
+        Ok(head == tail)
+    }
+
+    /// Appends `msg`, `false` if there is no room for it right now
+    pub fn push(&self, memory: &Memory, msg: &[u8]) -> Result<bool, Broken> {
+        let (head, tail) = self.positions(memory)?;
+        let used = tail.wrapping_sub(head);
+        let len = msg.len() as u32;
+        if !self.fits(msg.len()) || self.capacity - used < FRAME_HEADER + len {
+            return Ok(false);
+        }
+
+        let data = self.data(memory)?;
+        let frame = len.to_le_bytes().into_iter().chain(msg.iter().copied());
+        for (i, byte) in frame.enumerate() {
+            data[(tail as usize + i) % self.capacity as usize].set(byte);
+        }
+        self.set(memory, 1, tail.wrapping_add(FRAME_HEADER + len))?;
+        Ok(true)
+    }
+
+    pub fn pop(&self, memory: &Memory) -> Result<Option<Bytes>, Broken> {
+        let (head, tail) = self.positions(memory)?;
+        let used = tail.wrapping_sub(head);
+        if used == 0 {
+            return Ok(None);
+        }
+
+        let data = self.data(memory)?;
+        let byte_at = |pos: u32| data[(pos % self.capacity) as usize].get();
+        if used < FRAME_HEADER {
+            return Err(Broken);
+        }
+        let len = u32::from_le_bytes([0, 1, 2, 3].map(|i| byte_at(head.wrapping_add(i))));
+        if len > used - FRAME_HEADER {
+            return Err(Broken);
+        }
+        let msg = (0..len)
+            .map(|i| byte_at(head.wrapping_add(FRAME_HEADER + i)))
+            .collect::<Vec<_>>();
+        self.set(memory, 0, head.wrapping_add(FRAME_HEADER + len))?;
+
+        Ok(Some(msg.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmer::{MemoryType, Store};
+    use wasmer_engine_universal::Universal;
+
+    const HEADER: u32 = 0;
+    const DATA: u32 = 64;
+
+    /// A ring of `capacity` bytes at `DATA` with its header at `HEADER`
+    fn ring(capacity: u32) -> (Memory, Ring) {
+        // memories don't need a compiler
+        let store = Store::new(&Universal::headless().engine());
+        let memory = Memory::new(&store, MemoryType::new(1, None, false)).unwrap();
+        let cells = WasmPtr::<u32, Array>::new(HEADER).deref(&memory, 0, 4).unwrap();
+        cells[2].set(capacity);
+        cells[3].set(DATA);
+        let ring = Ring::new(&memory, HEADER).unwrap();
+        (memory, ring)
+    }
+
+    fn header(memory: &Memory) -> Vec<WasmCell<'_, u32>> {
+        WasmPtr::<u32, Array>::new(HEADER).deref(memory, 0, 4).unwrap()
+    }
+
+    fn set_positions(memory: &Memory, pos: u32) {
+        header(memory)[0].set(pos);
+        header(memory)[1].set(pos);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let (memory, _) = ring(16);
+        let cells = header(&memory);
+        cells[2].set(24);
+        assert!(Ring::new(&memory, HEADER).is_none());
+        cells[2].set(2);
+        assert!(Ring::new(&memory, HEADER).is_none());
+        // past the end of the single page
+        cells[2].set(1 << 16);
+        assert!(Ring::new(&memory, HEADER).is_none());
+    }
+
+    #[test]
+    fn empty_ring_has_nothing_to_pop() {
+        let (memory, ring) = ring(16);
+        assert_eq!(ring.is_empty(&memory), Ok(true));
+        assert_eq!(ring.pop(&memory), Ok(None));
+        assert_eq!(ring.push(&memory, b""), Ok(true));
+        assert_eq!(ring.is_empty(&memory), Ok(false));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &b""[..]);
+        assert_eq!(ring.is_empty(&memory), Ok(true));
+    }
+
+    #[test]
+    fn exactly_full() {
+        let (memory, ring) = ring(16);
+        assert_eq!(ring.push(&memory, &[7; 12]), Ok(true));
+        assert_eq!(ring.push(&memory, b""), Ok(false));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &[7; 12][..]);
+        assert_eq!(ring.push(&memory, &[8; 12]), Ok(true));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &[8; 12][..]);
+    }
+
+    #[test]
+    fn oversize_messages_never_fit() {
+        let (memory, ring) = ring(16);
+        assert!(ring.fits(12));
+        assert!(!ring.fits(13));
+        assert_eq!(ring.push(&memory, &[0; 13]), Ok(false));
+        assert_eq!(ring.is_empty(&memory), Ok(true));
+    }
+
+    #[test]
+    fn messages_wrap_around_the_end() {
+        let (memory, ring) = ring(16);
+        assert_eq!(ring.push(&memory, b"0123456789"), Ok(true));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &b"0123456789"[..]);
+        // starts at 14, the length and payload both wrap
+        assert_eq!(ring.push(&memory, b"abcdefghij"), Ok(true));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &b"abcdefghij"[..]);
+        assert_eq!(ring.push(&memory, b"klm"), Ok(true));
+        assert_eq!(ring.push(&memory, b"nop"), Ok(true));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &b"klm"[..]);
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &b"nop"[..]);
+        assert_eq!(ring.pop(&memory), Ok(None));
+    }
+
+    #[test]
+    fn positions_wrap_around_u32() {
+        let (memory, ring) = ring(16);
+        set_positions(&memory, u32::MAX - 5);
+        assert_eq!(ring.push(&memory, b"abcdef"), Ok(true));
+        assert_eq!(ring.push(&memory, b"abcdefg"), Ok(false));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &b"abcdef"[..]);
+        assert_eq!(header(&memory)[0].get(), 4);
+        assert_eq!(ring.push(&memory, &[1; 12]), Ok(true));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &[1; 12][..]);
+    }
+
+    #[test]
+    fn corrupted_header_breaks_the_ring() {
+        let (memory, ring) = ring(16);
+        let cells = header(&memory);
+        // the attached capacity and data stay in use
+        cells[2].set(0);
+        cells[3].set(u32::MAX);
+        assert_eq!(ring.push(&memory, b"abc"), Ok(true));
+        assert_eq!(ring.pop(&memory).unwrap().unwrap(), &b"abc"[..]);
+
+        // more in the ring than fits
+        cells[0].set(0);
+        cells[1].set(17);
+        assert_eq!(ring.is_empty(&memory), Err(Broken));
+        assert_eq!(ring.push(&memory, b"abc"), Err(Broken));
+        assert_eq!(ring.pop(&memory), Err(Broken));
+        cells[0].set(20);
+        cells[1].set(4);
+        assert_eq!(ring.pop(&memory), Err(Broken));
+
+        // a frame longer than what is in the ring
+        set_positions(&memory, 0);
+        assert_eq!(ring.push(&memory, b"abc"), Ok(true));
+        let data = WasmPtr::<u32>::new(DATA).deref(&memory).unwrap();
+        data.set(100);
+        assert_eq!(ring.pop(&memory), Err(Broken));
+        cells[1].set(2);
+        assert_eq!(ring.pop(&memory), Err(Broken));
+    }
+}
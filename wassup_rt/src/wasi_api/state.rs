@@ -1,19 +1,72 @@
-use std::collections::HashMap;
-use std::sync::atomic::AtomicU32;
-use std::sync::Mutex;
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use crossbeam_queue::SegQueue;
 use dashmap::DashMap;
+use crate::wasi_api::export::Completion;
 use crate::wasi_api::ipc::Ipc;
+use crate::wasi_api::log::GuestSpan;
+use crate::wasi_api::panic::GuestPanic;
+use crate::wasi_api::tasks::GuestTask;
+use crate::memory_limit::MemoryLimit;
+use crate::metrics::Metrics;
+use crate::trace::CallTrace;
+
+static INSTANCE_ID: AtomicU64 = AtomicU64::new(0);
 
 pub struct State {
+    /// used to tell instances apart in the host's logs
+    pub instance_id: u64,
+    /// returned by `args_get`
+    pub args: Vec<String>,
+    /// returned by `environ_get` as `key=value`
+    pub envs: Vec<(String, String)>,
+    /// embedder data for custom imports, see `WasiEnv::data`
+    pub data: Option<Arc<dyn Any + Send + Sync>>,
     pub ipcs: DashMap<u32, Ipc>,
+    /// channels the guest may have open at once
+    pub max_ipcs: usize,
+    /// channels the guest opened that the host hasn't accepted yet
+    pub new_ipcs: SegQueue<Ipc>,
     pub next_id: AtomicU32,
+    pub spans: DashMap<u64, GuestSpan>,
+    pub next_call_id: AtomicU64,
+    /// finished calls to guest exports by call id
+    pub completions: DashMap<u64, Completion>,
+    /// set through `InstanceBuilder::memory_limit`
+    pub memory_limit: Option<Arc<MemoryLimit>>,
+    /// reported by the guest's panic hook
+    pub panic: Mutex<Option<GuestPanic>>,
+    /// recorded by the hooks of a module compiled with call tracing
+    pub trace: Mutex<CallTrace>,
+    pub metrics: Arc<Metrics>,
+    /// the guest's answer to the last `__wassup_dump_tasks`
+    pub tasks: Mutex<Vec<GuestTask>>,
+    /// set by the watchdog, the guest runtime asks for it through `yield_requested`
+    pub yield_requested: AtomicBool,
 }
 
 impl State {
     pub fn new() -> Self {
+        let instance_id = INSTANCE_ID.fetch_add(1, Ordering::Relaxed);
         Self {
+            instance_id,
+            args: vec![],
+            envs: vec![],
+            data: None,
             ipcs: Default::default(),
-            next_id: Default::default()
+            max_ipcs: 128,
+            new_ipcs: SegQueue::new(),
+            next_id: Default::default(),
+            spans: Default::default(),
+            next_call_id: Default::default(),
+            completions: Default::default(),
+            memory_limit: None,
+            panic: Mutex::new(None),
+            trace: Default::default(),
+            metrics: Metrics::register(instance_id),
+            tasks: Mutex::new(vec![]),
+            yield_requested: AtomicBool::new(false),
         }
     }
 }
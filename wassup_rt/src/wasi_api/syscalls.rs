@@ -1,11 +1,13 @@
+use crate::instance::Shutdown;
 use crate::wasi_api::env::WasiEnv;
 use crate::wasi_api::unix::{platform_clock_res_get, platform_clock_time_get};
 
 use rand::RngCore;
+use std::borrow::Cow;
 use std::io::Write;
 use wasi::{Errno, ERRNO_ADDRNOTAVAIL, ERRNO_BADF, ERRNO_INVAL, ERRNO_IO, ERRNO_SUCCESS};
 
-use wasmer::{Array, WasmError, WasmPtr};
+use wasmer::{Array, Memory, WasmPtr};
 use wasmer_types::ValueType;
 
 macro_rules! deref_item (
@@ -37,19 +39,50 @@ pub struct Ciovec {
 
 unsafe impl ValueType for Ciovec {}
 
-pub fn args_get(
-    _env: &WasiEnv,
-    _argv: WasmPtr<WasmPtr<u8, Array>, Array>,
-    _argv_buf: WasmPtr<u8, Array>,
+/// Writes `strings` nul terminated into `buf` and a pointer to each of them into `ptrs`
+fn write_string_list<'a>(
+    memory: &Memory,
+    strings: impl Iterator<Item = Cow<'a, str>>,
+    ptrs: WasmPtr<WasmPtr<u8, Array>, Array>,
+    buf: WasmPtr<u8, Array>,
 ) -> Errno {
+    let mut offset = 0;
+    for (i, string) in strings.enumerate() {
+        let len = string.len() as u32 + 1;
+        let cells = deref_array!(buf, offset => len, memory);
+        for (cell, byte) in cells.iter().zip(string.bytes().chain([0])) {
+            cell.set(byte);
+        }
+        deref_array!(ptrs, i as u32 => 1, memory)[0]
+            .set(WasmPtr::new(buf.offset() + offset));
+        offset += len;
+    }
+
     ERRNO_SUCCESS
 }
 
+fn env_strings(env: &WasiEnv) -> impl Iterator<Item = Cow<'_, str>> {
+    env.state
+        .envs
+        .iter()
+        .map(|(key, value)| Cow::Owned(format!("{}={}", key, value)))
+}
+
+pub fn args_get(
+    env: &WasiEnv,
+    argv: WasmPtr<WasmPtr<u8, Array>, Array>,
+    argv_buf: WasmPtr<u8, Array>,
+) -> Errno {
+    let args = env.state.args.iter().map(|arg| Cow::Borrowed(arg.as_str()));
+    write_string_list(env.memory(), args, argv, argv_buf)
+}
+
 pub fn args_sizes_get(env: &WasiEnv, argc: WasmPtr<u32>, argv_buf_size: WasmPtr<u32>) -> Errno {
     let memory = env.memory();
+    let args = &env.state.args;
 
-    deref_item!(argc, memory).set(0);
-    deref_item!(argv_buf_size, memory).set(0);
+    deref_item!(argc, memory).set(args.len() as u32);
+    deref_item!(argv_buf_size, memory).set(args.iter().map(|arg| arg.len() as u32 + 1).sum());
 
     ERRNO_SUCCESS
 }
@@ -104,11 +137,11 @@ pub fn random_get(env: &WasiEnv, buf: WasmPtr<u8, Array>, buf_len: u32) -> Errno
 }
 
 pub fn environ_get(
-    _env: &WasiEnv,
-    _environ: WasmPtr<WasmPtr<u8, Array>, Array>,
-    _environ_buf: WasmPtr<u8, Array>,
+    env: &WasiEnv,
+    environ: WasmPtr<WasmPtr<u8, Array>, Array>,
+    environ_buf: WasmPtr<u8, Array>,
 ) -> Errno {
-    ERRNO_SUCCESS
+    write_string_list(env.memory(), env_strings(env), environ, environ_buf)
 }
 
 pub fn environ_sizes_get(
@@ -118,14 +151,15 @@ pub fn environ_sizes_get(
 ) -> Errno {
     let memory = env.memory();
 
-    deref_item!(environ_count, memory).set(0);
-    deref_item!(environ_buf_size, memory).set(0);
+    deref_item!(environ_count, memory).set(env.state.envs.len() as u32);
+    deref_item!(environ_buf_size, memory).set(env_strings(env).map(|s| s.len() as u32 + 1).sum());
 
     ERRNO_SUCCESS
 }
 
-pub fn proc_exit(_env: &WasiEnv, _error_code: wasi::Exitcode) -> Result<(), WasmError> {
-    Err(WasmError::Generic("proc_exit".to_string()))
+/// Unwinds like `shutdown_rt`, so the exit code ends up in `Status::Exited`
+pub fn proc_exit(_env: &WasiEnv, exit_code: wasi::Exitcode) -> Result<(), Shutdown> {
+    Err(Shutdown(exit_code))
 }
 
 pub fn fd_write(
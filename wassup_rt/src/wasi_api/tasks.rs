@@ -0,0 +1,106 @@
+use crate::wasi_api::env::WasiEnv;
+use std::fmt::{self, Display};
+use std::time::Duration;
+use wasmer::{Array, WasmPtr};
+use wasmer_types::ValueType;
+
+/// A task of the guest runtime, see [`GuestInstance::tasks`](crate::GuestInstance::tasks)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestTask {
+    pub id: u64,
+    pub name: Option<String>,
+    /// where the task was spawned
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+    pub polls: u64,
+    /// time the guest spent polling the task
+    pub busy: Duration,
+    pub longest_poll: Duration,
+    pub state: TaskState,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    /// being polled, a task that is running between calls into the guest is the one that trapped
+    Running,
+    /// woken and waiting for its turn
+    Scheduled,
+    /// waiting to be woken
+    Idle,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TaskRecord {
+    id: u64,
+    name: WasmPtr<u8, Array>,
+    name_len: u32,
+    file: WasmPtr<u8, Array>,
+    file_len: u32,
+    line: u32,
+    column: u32,
+    polls: u64,
+    busy_nanos: u64,
+    longest_poll_nanos: u64,
+    /// 0 = running, 1 = scheduled, 2 = idle
+    state: u32,
+}
+
+unsafe impl ValueType for TaskRecord {}
+
+pub fn report_tasks(env: &WasiEnv, records: WasmPtr<TaskRecord, Array>, count: u32) {
+    let records = match records.deref(env.memory(), 0, count) {
+        Some(cells) => cells.iter().map(|cell| cell.get()).collect::<Vec<_>>(),
+        None => return,
+    };
+    let read = |ptr: WasmPtr<u8, Array>, len| {
+        env.read_bytes(ptr, len)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default()
+    };
+    let tasks = records
+        .into_iter()
+        .map(|record| GuestTask {
+            id: record.id,
+            name: Some(read(record.name, record.name_len)).filter(|name| !name.is_empty()),
+            file: read(record.file, record.file_len),
+            line: record.line,
+            column: record.column,
+            polls: record.polls,
+            busy: Duration::from_nanos(record.busy_nanos),
+            longest_poll: Duration::from_nanos(record.longest_poll_nanos),
+            state: match record.state {
+                0 => TaskState::Running,
+                1 => TaskState::Scheduled,
+                _ => TaskState::Idle,
+            },
+        })
+        .collect();
+
+    *env.state.tasks.lock().unwrap() = tasks;
+}
+
+impl Display for GuestTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " `{}`", name)?;
+        }
+        write!(
+            f,
+            " ({}) spawned at {}:{}:{}, {} polls, busy {:?}, longest poll {:?}",
+            self.state, self.file, self.line, self.column, self.polls, self.busy, self.longest_poll
+        )
+    }
+}
+
+impl Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TaskState::Running => "running",
+            TaskState::Scheduled => "scheduled",
+            TaskState::Idle => "idle",
+        })
+    }
+}
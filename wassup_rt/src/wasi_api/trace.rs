@@ -0,0 +1,9 @@
+use crate::wasi_api::env::WasiEnv;
+
+pub fn trace_enter(env: &WasiEnv, function: u32) {
+    env.state.trace.lock().unwrap().enter(function);
+}
+
+pub fn trace_exit(env: &WasiEnv, function: u32) {
+    env.state.trace.lock().unwrap().exit(function);
+}
@@ -0,0 +1,167 @@
+use crate::wasi_api::State;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use wasmer::{Global, Value};
+
+/// A call into the guest ran past its deadline and was interrupted. The guest may have been left
+/// in the middle of anything, the instance shouldn't be polled again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineExceeded {
+    pub budget: Duration,
+}
+
+/// Watches the calls into one instance from a thread of its own. Once a call takes longer than
+/// the budget `yield_requested` is set, asking the guest runtime to return. If it still runs after
+/// another budget the interrupt global makes it trap.
+pub(crate) struct Watchdog {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Arc<State>,
+    budget: Duration,
+    interrupt: Option<Global>,
+    call: Mutex<Call>,
+    cond: Condvar,
+    interrupted: AtomicBool,
+}
+
+#[derive(Copy, Clone)]
+enum Call {
+    Idle,
+    Running {
+        started: Instant,
+        yielded: bool,
+    },
+    /// the interrupt global is set, nothing left to do until the call returns
+    Interrupted,
+    Stopped,
+}
+
+impl Watchdog {
+    pub fn spawn(state: Arc<State>, budget: Duration, interrupt: Option<Global>) -> Self {
+        let instance_id = state.instance_id;
+        let shared = Arc::new(Shared {
+            state,
+            budget,
+            interrupt,
+            call: Mutex::new(Call::Idle),
+            cond: Condvar::new(),
+            interrupted: AtomicBool::new(false),
+        });
+        let watched = shared.clone();
+        std::thread::Builder::new()
+            .name(format!("wassup-watchdog-{}", instance_id))
+            .spawn(move || watched.watch())
+            .expect("failed to spawn watchdog thread");
+
+        Self { shared }
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.shared.budget
+    }
+
+    /// Times `f`, returns whether the guest had to be interrupted
+    pub fn watch<R>(&self, f: impl FnOnce() -> R) -> (R, bool) {
+        *self.shared.call.lock().unwrap() = Call::Running {
+            started: Instant::now(),
+            yielded: false,
+        };
+        self.shared.cond.notify_one();
+
+        let result = f();
+
+        let mut call = self.shared.call.lock().unwrap();
+        if !matches!(*call, Call::Running { yielded: false, .. }) {
+            self.shared.reset();
+        }
+        *call = Call::Idle;
+        (
+            result,
+            self.shared.interrupted.swap(false, Ordering::AcqRel),
+        )
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        *self.shared.call.lock().unwrap() = Call::Stopped;
+        self.shared.cond.notify_one();
+    }
+}
+
+impl Shared {
+    fn watch(&self) {
+        let mut call = self.call.lock().unwrap();
+        loop {
+            let (started, yielded) = match *call {
+                Call::Running { started, yielded } => (started, yielded),
+                Call::Idle | Call::Interrupted => {
+                    call = self.cond.wait(call).unwrap();
+                    continue;
+                }
+                Call::Stopped => return,
+            };
+
+            let deadline = started
+                + if yielded {
+                    self.budget * 2
+                } else {
+                    self.budget
+                };
+            let now = Instant::now();
+            if now < deadline {
+                call = self.cond.wait_timeout(call, deadline - now).unwrap().0;
+                continue;
+            }
+
+            if !yielded {
+                log::warn!(
+                    "[instance {}] poll_runtime ran for over {:?}, asking it to yield",
+                    self.state.instance_id,
+                    self.budget,
+                );
+                self.state.yield_requested.store(true, Ordering::Release);
+                *call = Call::Running {
+                    started,
+                    yielded: true,
+                };
+            } else if let Some(interrupt) = &self.interrupt {
+                log::error!(
+                    "[instance {}] poll_runtime didn't yield within {:?}, interrupting it",
+                    self.state.instance_id,
+                    self.budget * 2,
+                );
+                self.interrupted.store(true, Ordering::Release);
+                let _ = interrupt.set(Value::I32(1));
+                *call = Call::Interrupted;
+            } else {
+                // the module wasn't instrumented, there is no way to stop it
+                *call = Call::Interrupted;
+            }
+        }
+    }
+
+    fn reset(&self) {
+        self.state.yield_requested.store(false, Ordering::Release);
+        if let Some(interrupt) = &self.interrupt {
+            let _ = interrupt.set(Value::I32(0));
+        }
+    }
+}
+
+impl Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "guest ran past its deadline of {:?} without yielding",
+            self.budget
+        )
+    }
+}
+
+impl Error for DeadlineExceeded {}
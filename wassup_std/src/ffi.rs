@@ -1,32 +1,110 @@
-use std::sync::atomic::Ordering;
 use bytes::{Bytes, BytesMut};
-use wasi::{Errno, ERRNO_NOENT, ERRNO_NXIO, ERRNO_SUCCESS};
+use wasi::{Errno, ERRNO_AGAIN, ERRNO_NOENT, ERRNO_NXIO, ERRNO_SUCCESS};
 use crate::ipc::IPCS;
-use crate::runtime::RUNTIME;
+use crate::ipc::RingHeader;
+use crate::runtime::{self, TaskState, RUNTIME};
 
 type Duration = u64;
 
 extern "C" {
     // async runtime interface
-    pub static yield_rt: u32;
+    /// non-zero once the host wants the runtime to return from `poll_runtime`
+    pub fn yield_requested() -> u32;
     pub fn wake();
-    pub fn shutdown_rt() -> !;
+    pub fn shutdown_rt(exit_code: u32) -> !;
 
     // ipc interface
-    /// returns:
-    ///  .0: channel id
-    ///  .1: buffer size
-    ///  .2: error
-    pub fn ipc_make_channel() -> IpcMakeChannelResult;
+    /// writes the new channel's id to `id`, a `capacity` of 0 picks the host's default
+    pub fn ipc_make_channel(capacity: u32, id: *mut u32) -> Errno;
     pub fn ipc_drop_channel(id: u32) -> Errno;
-    pub fn ipc_send_msg(buffer: *const u8, len_buf: usize) -> Errno;
-    pub fn ipc_recv_msg(buffer: *mut u8, len_buf: usize) -> Errno;
+    /// fails with `ERRNO_PIPE` once the host closed the channel
+    pub fn ipc_send_msg(id: u32, buffer: *const u8, len_buf: usize) -> Errno;
+    /// copies the message announced by `ipc_notify` into `buffer`
+    pub fn ipc_recv_msg(id: u32, buffer: *mut u8, len_buf: usize) -> Errno;
+    /// switches the channel over to shared rings, `tx` is written by the guest, `rx` by the host
+    pub fn ipc_attach_rings(id: u32, tx: *const RingHeader, rx: *const RingHeader) -> Errno;
+    /// makes the host take everything out of the channel's `tx` ring
+    pub fn ipc_ring_flush(id: u32) -> Errno;
+
+    // exports interface
+    /// `status` 0 means `result` holds the encoded output, otherwise an error message
+    pub fn export_complete(call_id: u64, status: u32, result: *const u8, result_len: usize);
+
+    // panic interface
+    /// called by the panic hook right before the guest aborts
+    pub fn report_panic(record: *const PanicRecord);
+
+    // metrics interface
+    /// called at the end of every poll
+    pub fn report_metrics(record: *const RuntimeMetrics);
+
+    // task introspection interface
+    /// answers `__wassup_dump_tasks`
+    pub fn report_tasks(records: *const TaskRecord, count: usize);
+
+    // logging interface
+    /// returns the most verbose level the host wants to see, 0 = off up to 5 = trace
+    pub fn log_max_level() -> u32;
+    pub fn log_event(record: *const LogRecord);
+    pub fn log_span_new(record: *const LogRecord);
+    pub fn log_span_record(id: u64, fields: *const u8, fields_len: usize);
+    pub fn log_span_close(id: u64);
 }
 
+/// A span or event as seen by the host, `fields` is encoded by `logging::FieldEncoder`
 #[repr(C)]
-pub struct IpcMakeChannelResult {
-    pub id: u32,
-    pub err: Errno,
+pub struct LogRecord {
+    /// 1 = error up to 5 = trace
+    pub level: u32,
+    /// id of the new span for `log_span_new`, the current span (or 0) for `log_event`
+    pub span: u64,
+    /// parent of the new span, or 0
+    pub parent: u64,
+    pub target: *const u8,
+    pub target_len: usize,
+    pub name: *const u8,
+    pub name_len: usize,
+    pub fields: *const u8,
+    pub fields_len: usize,
+    pub file: *const u8,
+    pub file_len: usize,
+    pub line: u32,
+}
+
+#[repr(C)]
+pub struct PanicRecord {
+    pub message: *const u8,
+    pub message_len: usize,
+    pub file: *const u8,
+    pub file_len: usize,
+    pub line: u32,
+    pub column: u32,
+    /// the task that panicked, `u64::MAX` if it happened outside of a task
+    pub task: u64,
+}
+
+#[repr(C)]
+pub struct RuntimeMetrics {
+    pub tasks: u32,
+    pub timers: u32,
+    /// tasks woken but not polled yet
+    pub poll_queue: u32,
+}
+
+#[repr(C)]
+pub struct TaskRecord {
+    pub id: u64,
+    pub name: *const u8,
+    pub name_len: usize,
+    pub file: *const u8,
+    pub file_len: usize,
+    pub line: u32,
+    pub column: u32,
+    pub polls: u64,
+    pub busy_nanos: u64,
+    pub longest_poll_nanos: u64,
+    /// 0 = running, 1 = scheduled, 2 = idle
+    pub state: u32,
 }
 
 #[no_mangle]
@@ -34,7 +112,7 @@ pub extern "C" fn poll_runtime() -> Duration {
     let dur = RUNTIME.with(|rt| {
         loop {
             let next = rt.poll();
-            if next != 0 || unsafe { yield_rt } != 0 {
+            if next != 0 || unsafe { yield_requested() } != 0 {
                 break next;
             }
         }
@@ -45,7 +123,7 @@ pub extern "C" fn poll_runtime() -> Duration {
 #[no_mangle]
 pub extern "C" fn ipc_notify(id: u32, seg_size: u32) -> Errno {
     IPCS.with(|ipcs| {
-        let mut map = ipcs.borrow_mut();
+        let map = ipcs.borrow();
 
         let rc = map
             .get(&id)
@@ -54,17 +132,28 @@ pub extern "C" fn ipc_notify(id: u32, seg_size: u32) -> Errno {
         if let Some(rc) = rc {
             let mut receiver = (*rc).borrow_mut();
 
+            // a size of 0 on a ring channel means the host filled the empty `rx` ring
+            if seg_size == 0 && receiver.rx_ring.is_some() {
+                if let Some(waker) = receiver.waker.take() {
+                    waker.wake();
+                }
+                return ERRNO_SUCCESS;
+            }
+
+            if receiver.buffer.len() >= receiver.capacity {
+                // the host keeps the message until the receiver caught up
+                return ERRNO_AGAIN;
+            }
             let mut buf = vec![0u8; seg_size as usize];
-            let err = unsafe { ipc_recv_msg(buf.as_mut_ptr(), buf.len()) };
+            let err = unsafe { ipc_recv_msg(id, buf.as_mut_ptr(), buf.len()) };
             if err != ERRNO_SUCCESS {
                 return err;
             }
             let bytes = Bytes::from(buf);
             receiver.buffer.push_back(bytes);
 
-            if let Some(waker) = &receiver.waker {
-                receiver.flag.store(true, Ordering::Release);
-                waker.wake_by_ref();
+            if let Some(waker) = receiver.waker.take() {
+                waker.wake();
             }
 
             return ERRNO_SUCCESS;
@@ -72,4 +161,92 @@ pub extern "C" fn ipc_notify(id: u32, seg_size: u32) -> Errno {
 
         ERRNO_NXIO
     })
-}
\ No newline at end of file
+}
+
+/// The host asks for a snapshot of the tasks, e.g. to show what a stuck guest was doing
+#[no_mangle]
+pub extern "C" fn __wassup_dump_tasks() {
+    let tasks = runtime::dump_tasks();
+    let records = tasks
+        .iter()
+        .map(|task| {
+            let name = task.name.as_deref().unwrap_or("");
+            TaskRecord {
+                id: task.id as u64,
+                name: name.as_ptr(),
+                name_len: name.len(),
+                file: task.location.file().as_ptr(),
+                file_len: task.location.file().len(),
+                line: task.location.line(),
+                column: task.location.column(),
+                polls: task.polls,
+                busy_nanos: task.busy.as_nanos() as u64,
+                longest_poll_nanos: task.longest_poll.as_nanos() as u64,
+                state: match task.state {
+                    TaskState::Running => 0,
+                    TaskState::Scheduled => 1,
+                    TaskState::Idle => 2,
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    unsafe { report_tasks(records.as_ptr(), records.len()) };
+}
+
+/// Buffer for the host to pass arguments to an exported handler, freed by the handler's entry point.
+/// The host also allocates the profiler's shadow stack and the coverage counters through it, they
+/// are never freed.
+#[no_mangle]
+pub extern "C" fn __wassup_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// The `Codec::ID` exports are called with, checked by the host before every call
+#[cfg(any(feature = "serde_json", feature = "bincode", feature = "postcard"))]
+#[no_mangle]
+pub extern "C" fn __wassup_codec() -> u32 {
+    use crate::ipc::codec::{Codec, DefaultCodec};
+    DefaultCodec::ID
+}
+
+#[cfg(any(feature = "serde_json", feature = "bincode", feature = "postcard"))]
+pub fn complete_export(call_id: u64, result: Result<Vec<u8>, String>) {
+    let (status, bytes) = match result {
+        Ok(bytes) => (0, bytes),
+        Err(msg) => (1, msg.into_bytes()),
+    };
+    unsafe { export_complete(call_id, status, bytes.as_ptr(), bytes.len()) };
+}
+
+/// The host made room in a channel a send failed on
+#[no_mangle]
+pub extern "C" fn ipc_writable(id: u32) {
+    IPCS.with(|ipcs| {
+        let rc = ipcs.borrow().get(&id).and_then(|recv| recv.upgrade());
+        if let Some(rc) = rc {
+            let mut receiver = (*rc).borrow_mut();
+            receiver.writable = true;
+            if let Some(waker) = receiver.send_waker.take() {
+                waker.wake();
+            }
+        }
+    })
+}
+
+/// The host closed a channel, after delivering everything it sent before
+#[no_mangle]
+pub extern "C" fn ipc_closed(id: u32) {
+    IPCS.with(|ipcs| {
+        let rc = ipcs.borrow().get(&id).and_then(|recv| recv.upgrade());
+        if let Some(rc) = rc {
+            let mut receiver = (*rc).borrow_mut();
+            receiver.closed = true;
+            if let Some(waker) = receiver.waker.take() {
+                waker.wake();
+            }
+            if let Some(waker) = receiver.send_waker.take() {
+                waker.wake();
+            }
+        }
+    })
+}
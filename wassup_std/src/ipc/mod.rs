@@ -0,0 +1,324 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+use bytes::Bytes;
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+use wasi::{ERRNO_AGAIN, ERRNO_PIPE, ERRNO_SUCCESS};
+use crate::ffi;
+use ring::Ring;
+
+mod ring;
+#[cfg(any(feature = "serde_json", feature = "bincode", feature = "postcard"))]
+mod typed;
+
+#[cfg(any(feature = "serde_json", feature = "bincode", feature = "postcard"))]
+pub use typed::{RpcError, TypedIpc};
+#[cfg(any(feature = "serde_json", feature = "bincode", feature = "postcard"))]
+pub use wassup_codec as codec;
+
+pub(crate) use ring::RingHeader;
+
+/// The capacity the host picks for channels created with 0
+const DEFAULT_CAPACITY: u32 = 128;
+
+type RefMap<K, V> = RefCell<HashMap<K, V>>;
+type SharedIpcReceiver = Rc<RefCell<IpcReceiver>>;
+type WeaklySharedIpcReceiver = Weak<RefCell<IpcReceiver>>;
+
+thread_local! {
+    pub(crate) static IPCS: RefMap<u32, WeaklySharedIpcReceiver> =
+        RefCell::new(HashMap::new());
+}
+
+/// A message channel to the host, the host picks it up with `GuestInstance::accept_ipc`
+pub struct Ipc {
+    id: u32,
+    receiver: SharedIpcReceiver,
+    /// guest -> host, see `with_rings`
+    tx_ring: Option<Ring>,
+}
+
+pub(crate) struct IpcReceiver {
+    /// messages copied in through `ipc_notify`, they come before the ones in `rx_ring`
+    pub buffer: VecDeque<Bytes>,
+    /// of the channel, the host keeps further messages until `buffer` has room again
+    pub capacity: usize,
+    /// host -> guest
+    pub rx_ring: Option<Ring>,
+    pub waker: Option<Waker>,
+    /// set by `ipc_writable` once the host made room after a send failed with `Full`
+    pub writable: bool,
+    pub send_waker: Option<Waker>,
+    /// set by `ipc_closed` or a send failing with `EPIPE`
+    pub closed: bool,
+}
+
+/// The host closed the channel, holds the message that couldn't be sent
+pub struct SendError(pub Bytes);
+
+pub enum TrySendError {
+    /// The host hasn't taken enough messages yet, holds the message that couldn't be sent
+    Full(Bytes),
+    /// The host closed the channel
+    Closed(Bytes),
+}
+
+struct Recv<'a>(&'a mut Ipc);
+
+struct SendMsg<'a> {
+    ipc: &'a mut Ipc,
+    msg: Option<Bytes>,
+}
+
+impl Ipc {
+    /// Creates a channel with the host's default capacity
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Creates a channel whose host side buffers up to `capacity` messages in each direction,
+    /// 0 picks the host's default
+    pub fn with_capacity(capacity: u32) -> Self {
+        let mut id = 0;
+        let err = unsafe { ffi::ipc_make_channel(capacity, &mut id) };
+        if err != wasi::ERRNO_SUCCESS {
+            panic!("ipc_make_channel failed: {} ({}): {}", wasi::errno_name(err), err, wasi::errno_docs(err));
+        }
+
+        let receiver = Rc::new(
+            RefCell::new(
+                IpcReceiver {
+                    buffer: VecDeque::with_capacity(16),
+                    capacity: match capacity {
+                        0 => DEFAULT_CAPACITY as usize,
+                        capacity => capacity as usize,
+                    },
+                    rx_ring: None,
+                    waker: None,
+                    writable: false,
+                    send_waker: None,
+                    closed: false,
+                },
+            ),
+        );
+
+        IPCS.with(|ipcs| {
+            ipcs.borrow_mut().insert(id, Rc::downgrade(&receiver));
+        });
+
+        Self {
+            id,
+            receiver,
+            tx_ring: None,
+        }
+    }
+
+    /// Creates a channel that passes messages through two ring buffers of `capacity` bytes
+    /// shared with the host, instead of a host call and copy per message. The host only gets
+    /// to the guest once a ring turns non-empty.
+    pub fn with_rings(capacity: u32) -> Self {
+        let mut ipc = Self::new();
+        let tx_ring = Ring::new(capacity);
+        let rx_ring = Ring::new(capacity);
+        let err = unsafe { ffi::ipc_attach_rings(ipc.id, tx_ring.header_ptr(), rx_ring.header_ptr()) };
+        if err != wasi::ERRNO_SUCCESS {
+            panic!("ipc_attach_rings failed: {} ({}): {}", wasi::errno_name(err), err, wasi::errno_docs(err));
+        }
+
+        ipc.tx_ring = Some(tx_ring);
+        (*ipc.receiver).borrow_mut().rx_ring = Some(rx_ring);
+        ipc
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Whether the host closed the channel, messages it sent before may still be buffered
+    pub fn is_closed(&self) -> bool {
+        (*self.receiver).borrow().closed
+    }
+
+    /// Sends `msg`, waiting for the host to make room if the channel is full. Fails once the host
+    /// closed the channel.
+    pub async fn send(&mut self, msg: Bytes) -> Result<(), SendError> {
+        SendMsg {
+            ipc: self,
+            msg: Some(msg),
+        }
+        .await
+    }
+
+    pub fn try_send(&mut self, msg: Bytes) -> Result<(), TrySendError> {
+        if self.is_closed() {
+            return Err(TrySendError::Closed(msg));
+        }
+
+        if let Some(ring) = &self.tx_ring {
+            if ring.push(&msg) {
+                return Ok(());
+            }
+            // let the host drain the ring, too large messages go the slow way behind it
+            match unsafe { ffi::ipc_ring_flush(self.id) } {
+                ERRNO_SUCCESS => {}
+                ERRNO_AGAIN => return Err(self.full(msg)),
+                ERRNO_PIPE => return Err(self.closed(msg)),
+                err => panic!("ipc_ring_flush failed: {}", wasi::errno_name(err)),
+            }
+            if ring.push(&msg) {
+                return Ok(());
+            }
+        }
+
+        match unsafe { ffi::ipc_send_msg(self.id, msg.as_ptr(), msg.len()) } {
+            ERRNO_SUCCESS => Ok(()),
+            ERRNO_AGAIN => Err(self.full(msg)),
+            ERRNO_PIPE => Err(self.closed(msg)),
+            err => panic!("ipc_send_msg failed: {}", wasi::errno_name(err)),
+        }
+    }
+
+    fn full(&self, msg: Bytes) -> TrySendError {
+        (*self.receiver).borrow_mut().writable = false;
+        TrySendError::Full(msg)
+    }
+
+    fn closed(&self, msg: Bytes) -> TrySendError {
+        (*self.receiver).borrow_mut().closed = true;
+        TrySendError::Closed(msg)
+    }
+
+    /// Resolves once the host made room after a send failed with `Full`, or closed the channel
+    pub fn poll_writable(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut receiver = (*self.receiver).borrow_mut();
+        if receiver.writable || receiver.closed {
+            Poll::Ready(())
+        } else {
+            receiver.send_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Waits for the next message, `None` once the host closed the channel and everything it
+    /// sent before was received
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        Recv(self).await
+    }
+
+    pub fn try_recv(&mut self) -> Option<Bytes> {
+        self.try_recv_with(Bytes::copy_from_slice)
+    }
+
+    /// Hands the next message to `f`, without copying it out of the ring first
+    pub fn try_recv_with<R>(&mut self, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        let mut receiver = (*self.receiver).borrow_mut();
+        match receiver.buffer.pop_front() {
+            Some(msg) => Some(f(&msg)),
+            None => receiver.rx_ring.as_ref()?.pop_with(f),
+        }
+    }
+
+    /// Takes the next message, or registers `cx` to be woken once one arrives
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        if let Some(msg) = self.try_recv() {
+            return Poll::Ready(Some(msg));
+        }
+
+        let mut receiver = (*self.receiver).borrow_mut();
+        if receiver.closed {
+            Poll::Ready(None)
+        } else {
+            receiver.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Default for Ipc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Ipc {
+    fn drop(&mut self) {
+        IPCS.with(|ipcs| ipcs.borrow_mut().remove(&self.id));
+        // the rings are freed after this, once the host forgot about them
+        assert_eq!(unsafe { ffi::ipc_drop_channel(self.id) }, wasi::ERRNO_SUCCESS);
+    }
+}
+
+impl Future for Recv<'_> {
+    type Output = Option<Bytes>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.poll_recv(cx)
+    }
+}
+
+impl Future for SendMsg<'_> {
+    type Output = Result<(), SendError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        while let Some(msg) = this.msg.take() {
+            match this.ipc.try_send(msg) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(msg)) => return Poll::Ready(Err(SendError(msg))),
+                Err(TrySendError::Full(msg)) => {
+                    this.msg = Some(msg);
+                    if this.ipc.poll_writable(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl TrySendError {
+    pub fn into_inner(self) -> Bytes {
+        match self {
+            TrySendError::Full(msg) | TrySendError::Closed(msg) => msg,
+        }
+    }
+}
+
+impl Debug for TrySendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl Display for TrySendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("channel is full"),
+            TrySendError::Closed(_) => f.write_str("channel is closed"),
+        }
+    }
+}
+
+impl Error for TrySendError {}
+
+impl Debug for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("channel is closed")
+    }
+}
+
+impl Error for SendError {}
@@ -0,0 +1,177 @@
+use std::cell::{Cell, UnsafeCell};
+
+/// Bytes in front of every message holding its length
+const FRAME_HEADER: u32 = 4;
+
+/// Shared with the host, which finds the data through `data`. `head` and `tail` count bytes read
+/// and written and wrap around, messages are a little endian `u32` length followed by the payload.
+#[repr(C)]
+pub(crate) struct RingHeader {
+    head: Cell<u32>,
+    tail: Cell<u32>,
+    capacity: u32,
+    data: *mut u8,
+}
+
+/// A single producer, single consumer ring buffer in linear memory
+pub(crate) struct Ring {
+    header: Box<RingHeader>,
+    data: Box<[UnsafeCell<u8>]>,
+}
+
+impl Ring {
+    /// `capacity` is rounded up to a power of two so positions can wrap around
+    pub fn new(capacity: u32) -> Self {
+        let capacity = capacity.max(FRAME_HEADER).next_power_of_two();
+        let data = (0..capacity).map(|_| UnsafeCell::new(0)).collect::<Box<[_]>>();
+        let header = Box::new(RingHeader {
+            head: Cell::new(0),
+            tail: Cell::new(0),
+            capacity,
+            data: UnsafeCell::raw_get(data.as_ptr()),
+        });
+
+        Self { header, data }
+    }
+
+    pub fn header_ptr(&self) -> *const RingHeader {
+        &*self.header
+    }
+
+    /// Whether a message of `len` bytes can ever fit
+    pub fn fits(&self, len: usize) -> bool {
+        len as u64 + FRAME_HEADER as u64 <= self.header.capacity as u64
+    }
+
+    /// Appends `msg`, returns `false` if there is no room for it right now
+    pub fn push(&self, msg: &[u8]) -> bool {
+        let header = &*self.header;
+        let tail = header.tail.get();
+        let used = tail.wrapping_sub(header.head.get());
+        let len = msg.len() as u32;
+        if !self.fits(msg.len()) || header.capacity - used < FRAME_HEADER + len {
+            return false;
+        }
+
+        self.write_at(tail, &len.to_le_bytes());
+        self.write_at(tail.wrapping_add(FRAME_HEADER), msg);
+        header.tail.set(tail.wrapping_add(FRAME_HEADER + len));
+        true
+    }
+
+    /// Hands the next message to `f`, it borrows the ring unless the message wraps around
+    pub fn pop_with<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Option<R> {
+        let header = &*self.header;
+        let head = header.head.get();
+        if head == header.tail.get() {
+            return None;
+        }
+
+        let mut len = [0; FRAME_HEADER as usize];
+        self.read_at(head, &mut len);
+        let len = u32::from_le_bytes(len);
+        let start = (head.wrapping_add(FRAME_HEADER) % header.capacity) as usize;
+
+        let result = if start + len as usize <= header.capacity as usize {
+            // SAFETY: the host only writes behind `tail`, so these bytes are ours until `head` moves
+            let data = unsafe { std::slice::from_raw_parts(header.data.add(start), len as usize) };
+            f(data)
+        } else {
+            let mut data = vec![0; len as usize];
+            self.read_at(head.wrapping_add(FRAME_HEADER), &mut data);
+            f(&data)
+        };
+        header.head.set(head.wrapping_add(FRAME_HEADER + len));
+        Some(result)
+    }
+
+    fn write_at(&self, pos: u32, bytes: &[u8]) {
+        let capacity = self.header.capacity as usize;
+        let start = pos as usize % capacity;
+        for (i, byte) in bytes.iter().enumerate() {
+            // SAFETY: in bounds and only the producer writes to the free part of the ring
+            unsafe { *self.data[(start + i) % capacity].get() = *byte };
+        }
+    }
+
+    fn read_at(&self, pos: u32, bytes: &mut [u8]) {
+        let capacity = self.header.capacity as usize;
+        let start = pos as usize % capacity;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            // SAFETY: in bounds and the producer doesn't touch the used part of the ring
+            *byte = unsafe { *self.data[(start + i) % capacity].get() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop(ring: &Ring) -> Option<Vec<u8>> {
+        ring.pop_with(|msg| msg.to_vec())
+    }
+
+    #[test]
+    fn empty_ring_has_nothing_to_pop() {
+        let ring = Ring::new(16);
+        assert_eq!(pop(&ring), None);
+        assert!(ring.push(b""));
+        assert_eq!(pop(&ring), Some(vec![]));
+        assert_eq!(pop(&ring), None);
+    }
+
+    #[test]
+    fn capacity_is_rounded_up() {
+        assert_eq!(Ring::new(0).header.capacity, FRAME_HEADER);
+        assert_eq!(Ring::new(17).header.capacity, 32);
+    }
+
+    #[test]
+    fn exactly_full() {
+        let ring = Ring::new(16);
+        assert!(ring.push(&[7; 12]));
+        // not even an empty message is left room for
+        assert!(!ring.push(b""));
+        assert_eq!(pop(&ring), Some(vec![7; 12]));
+        assert!(ring.push(&[8; 12]));
+        assert_eq!(pop(&ring), Some(vec![8; 12]));
+    }
+
+    #[test]
+    fn oversize_messages_never_fit() {
+        let ring = Ring::new(16);
+        assert!(ring.fits(12));
+        assert!(!ring.fits(13));
+        assert!(!ring.push(&[0; 13]));
+        assert_eq!(pop(&ring), None);
+    }
+
+    #[test]
+    fn messages_wrap_around_the_end() {
+        let ring = Ring::new(16);
+        assert!(ring.push(b"0123456789"));
+        assert_eq!(pop(&ring), Some(b"0123456789".to_vec()));
+        // starts at 14, the length and payload both wrap
+        assert!(ring.push(b"abcdefghij"));
+        assert_eq!(pop(&ring), Some(b"abcdefghij".to_vec()));
+        assert!(ring.push(b"klm"));
+        assert!(ring.push(b"nop"));
+        assert_eq!(pop(&ring), Some(b"klm".to_vec()));
+        assert_eq!(pop(&ring), Some(b"nop".to_vec()));
+        assert_eq!(pop(&ring), None);
+    }
+
+    #[test]
+    fn positions_wrap_around_u32() {
+        let ring = Ring::new(16);
+        ring.header.head.set(u32::MAX - 5);
+        ring.header.tail.set(u32::MAX - 5);
+        assert!(ring.push(b"abcdef"));
+        assert!(!ring.push(b"abcdefg"));
+        assert_eq!(pop(&ring), Some(b"abcdef".to_vec()));
+        assert_eq!(ring.header.head.get(), 4);
+        assert!(ring.push(&[1; 12]));
+        assert_eq!(pop(&ring), Some(vec![1; 12]));
+    }
+}
@@ -0,0 +1,233 @@
+use super::codec::{Codec, CodecError, DefaultCodec, HANDSHAKE_ID};
+use super::{Ipc, TrySendError};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// Request ids are sent as a little endian `u64` in front of the encoded payload
+const HEADER_LEN: usize = 8;
+
+/// Request/response calls to a host serving the channel with `wassup::rpc::RpcServer`.
+///
+/// Calls may run concurrently, responses are matched back to their request by id.
+pub struct TypedIpc<Req, Resp, C = DefaultCodec> {
+    shared: RefCell<Shared>,
+    _phantom: PhantomData<fn(Req, C) -> Resp>,
+}
+
+struct Shared {
+    ipc: Ipc,
+    next_id: u64,
+    /// responses received while polling for another call
+    responses: HashMap<u64, Bytes>,
+    waiters: HashMap<u64, Waker>,
+    /// calls waiting for room in the channel
+    send_waiters: Vec<Waker>,
+    /// the handshake, if it didn't fit into the channel right away
+    handshake: Option<Bytes>,
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    Codec(CodecError),
+    /// The host sent a response too short to hold a request id
+    Malformed,
+    /// The host closed the channel
+    Closed,
+}
+
+impl<Req, Resp, C> TypedIpc<Req, Resp, C>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    C: Codec,
+{
+    pub fn new() -> Self {
+        Self::from_ipc(Ipc::new())
+    }
+
+    /// Opens the channel with a handshake, the host checks that it decodes with the same codec
+    pub fn from_ipc(mut ipc: Ipc) -> Self {
+        let mut handshake = HANDSHAKE_ID.to_le_bytes().to_vec();
+        handshake.extend_from_slice(&C::ID.to_le_bytes());
+        let handshake = match ipc.try_send(handshake.into()) {
+            Err(TrySendError::Full(frame)) => Some(frame),
+            _ => None,
+        };
+        Self {
+            shared: RefCell::new(Shared {
+                ipc,
+                next_id: 0,
+                responses: HashMap::new(),
+                waiters: HashMap::new(),
+                send_waiters: vec![],
+                handshake,
+            }),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.shared.borrow().ipc.id()
+    }
+
+    pub async fn call(&self, req: &Req) -> Result<Resp, RpcError> {
+        let handshake = self.shared.borrow_mut().handshake.take();
+        if handshake.is_some() {
+            SendFrame {
+                shared: &self.shared,
+                frame: handshake,
+            }
+            .await?;
+        }
+
+        let mut frame = Vec::with_capacity(64);
+        let id = {
+            let mut shared = self.shared.borrow_mut();
+            let id = shared.next_id;
+            shared.next_id += 1;
+            id
+        };
+        frame.extend_from_slice(&id.to_le_bytes());
+        C::encode(req, &mut frame).map_err(RpcError::Codec)?;
+        SendFrame {
+            shared: &self.shared,
+            frame: Some(frame.into()),
+        }
+        .await?;
+
+        let resp = Response {
+            shared: &self.shared,
+            id,
+        }
+        .await?;
+        C::decode(&resp[HEADER_LEN..]).map_err(RpcError::Codec)
+    }
+}
+
+impl<Req, Resp, C> Default for TypedIpc<Req, Resp, C>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    C: Codec,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct SendFrame<'a> {
+    shared: &'a RefCell<Shared>,
+    frame: Option<Bytes>,
+}
+
+impl Future for SendFrame<'_> {
+    type Output = Result<(), RpcError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut shared = this.shared.borrow_mut();
+        while let Some(frame) = this.frame.take() {
+            match shared.ipc.try_send(frame) {
+                Ok(()) => break,
+                Err(TrySendError::Closed(_)) => {
+                    shared.send_waiters.drain(..).for_each(Waker::wake);
+                    return Poll::Ready(Err(RpcError::Closed));
+                }
+                Err(TrySendError::Full(frame)) => {
+                    this.frame = Some(frame);
+                    if shared.ipc.poll_writable(cx).is_pending() {
+                        shared.send_waiters.push(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+
+        // the channel only remembers one waker, let the others retry too
+        shared.send_waiters.drain(..).for_each(Waker::wake);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Resolves to the whole response frame of request `id`
+struct Response<'a> {
+    shared: &'a RefCell<Shared>,
+    id: u64,
+}
+
+impl Future for Response<'_> {
+    type Output = Result<Bytes, RpcError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.borrow_mut();
+        if let Some(resp) = shared.responses.remove(&self.id) {
+            return Poll::Ready(Ok(resp));
+        }
+
+        while let Poll::Ready(msg) = shared.ipc.poll_recv(cx) {
+            let msg = match msg {
+                Some(msg) => msg,
+                None => {
+                    // the other calls won't get a response either
+                    shared.waiters.drain().for_each(|(_, waker)| waker.wake());
+                    return Poll::Ready(Err(RpcError::Closed));
+                }
+            };
+            if msg.len() < HEADER_LEN {
+                return Poll::Ready(Err(RpcError::Malformed));
+            }
+            let id = u64::from_le_bytes(msg[..HEADER_LEN].try_into().unwrap());
+            if id == self.id {
+                return Poll::Ready(Ok(msg));
+            }
+            if let Some(waker) = shared.waiters.remove(&id) {
+                shared.responses.insert(id, msg);
+                waker.wake();
+            }
+        }
+
+        shared.waiters.insert(self.id, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Response<'_> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.waiters.remove(&self.id);
+        shared.responses.remove(&self.id);
+        // the channel only remembers the last waker, which may have been ours
+        if let Some(&id) = shared.waiters.keys().next() {
+            let waker = shared.waiters.remove(&id).unwrap();
+            waker.wake();
+        }
+    }
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Codec(err) => Display::fmt(err, f),
+            RpcError::Malformed => f.write_str("malformed response"),
+            RpcError::Closed => f.write_str("channel is closed"),
+        }
+    }
+}
+
+impl Error for RpcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RpcError::Codec(err) => Some(err),
+            RpcError::Malformed | RpcError::Closed => None,
+        }
+    }
+}
@@ -1,36 +1,87 @@
 mod ffi;
-mod runtime;
+mod logging;
+#[doc(hidden)]
+pub mod macro_support;
+pub mod runtime;
+pub mod sync;
 pub mod time;
 mod r#yield;
-mod ipc;
+pub mod ipc;
 
 use runtime::RUNTIME;
+use std::any::Any;
 use std::future::Future;
+use std::panic::Location;
 
+pub use logging::HostSubscriber;
 pub use r#yield::*;
 pub use runtime::JoinHandle;
 pub use tracing::{self, debug, error, info, trace, warn};
 
 pub use wassup_std_macros::async_main as main;
+pub use wassup_std_macros::{export, import, join, select, test, try_join};
 
+#[track_caller]
 pub fn spawn<R: 'static>(future: impl Future<Output = R> + 'static) -> JoinHandle<R> {
-    RUNTIME.with(|rt| rt.spawn(future))
+    let location = Location::caller();
+    RUNTIME.with(|rt| rt.spawn_at(None, location, future))
+}
+
+/// Spawns a task that goes by `name` in [`runtime::dump_tasks`]
+#[track_caller]
+pub fn spawn_named<R: 'static>(
+    name: impl Into<String>,
+    future: impl Future<Output = R> + 'static,
+) -> JoinHandle<R> {
+    let location = Location::caller();
+    RUNTIME.with(|rt| rt.spawn_at(Some(name.into()), location, future))
 }
 
 #[doc(hidden)]
 pub fn startup_runtime() {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::level_filters::LevelFilter::TRACE)
-        .init();
-
-    trace!("Hello from wassup_std!");
-    debug!("Hello from wassup_std!");
-    info!("Hello from wassup_std!");
-    warn!("Hello from wassup_std!");
-    error!("Hello from wassup_std!");
+    // a subscriber may already be installed if the runtime gets started twice
+    let _ = tracing::subscriber::set_global_default(HostSubscriber::new());
+    // replaces the default hook, the host prints the panic along with the trap that follows
+    std::panic::set_hook(Box::new(|info| {
+        report_panic(info.payload(), info.location())
+    }));
+}
+
+/// Hands the panic to the host through `report_panic`
+fn report_panic(payload: &(dyn Any + Send), location: Option<&Location<'_>>) {
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "Box<dyn Any>".to_string(),
+        },
+    };
+    let (file, line, column) = location.map_or(("", 0, 0), |location| {
+        (location.file(), location.line(), location.column())
+    });
+
+    let record = ffi::PanicRecord {
+        message: message.as_ptr(),
+        message_len: message.len(),
+        file: file.as_ptr(),
+        file_len: file.len(),
+        line,
+        column,
+        task: RUNTIME
+            .try_with(|rt| rt.current_task())
+            .ok()
+            .flatten()
+            .map_or(u64::MAX, |task| task as u64),
+    };
+    unsafe { ffi::report_panic(&record) };
 }
 
 #[doc(hidden)]
 pub fn shutdown_runtime() {
     RUNTIME.with(|rt| rt.shutdown());
 }
+
+#[doc(hidden)]
+pub fn exit_runtime(exit_code: u32) {
+    RUNTIME.with(|rt| rt.exit(exit_code));
+}
@@ -0,0 +1,210 @@
+use crate::ffi::{self, LogRecord};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing::level_filters::LevelFilter;
+
+thread_local! {
+    static SPAN_STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    static SPAN_REFS: RefCell<HashMap<u64, usize>> = RefCell::new(HashMap::new());
+}
+
+/// Forwards spans and events to the host, which filters, tags and prints them.
+pub struct HostSubscriber {
+    max_level: LevelFilter,
+    next_id: AtomicU64,
+}
+
+/// Encodes fields as `key_len: u32, key, value_len: u32, value` in little endian
+#[derive(Default)]
+struct FieldEncoder(Vec<u8>);
+
+impl HostSubscriber {
+    pub fn new() -> Self {
+        let max_level = match unsafe { ffi::log_max_level() } {
+            0 => LevelFilter::OFF,
+            1 => LevelFilter::ERROR,
+            2 => LevelFilter::WARN,
+            3 => LevelFilter::INFO,
+            4 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        };
+
+        Self {
+            max_level,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn current_span() -> u64 {
+        SPAN_STACK.with(|stack| stack.borrow().last().copied().unwrap_or(0))
+    }
+}
+
+impl Default for HostSubscriber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Subscriber for HostSubscriber {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.enabled(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= &self.max_level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.max_level)
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let parent = if span.is_contextual() {
+            Self::current_span()
+        } else {
+            span.parent().map(Id::into_u64).unwrap_or(0)
+        };
+
+        let mut fields = FieldEncoder::default();
+        span.record(&mut fields);
+
+        let record = LogRecord::new(span.metadata(), id, parent, &fields.0);
+        unsafe { ffi::log_span_new(&record) };
+
+        SPAN_REFS.with(|refs| refs.borrow_mut().insert(id, 1));
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = FieldEncoder::default();
+        values.record(&mut fields);
+
+        let id = span.into_u64();
+        unsafe { ffi::log_span_record(id, fields.0.as_ptr(), fields.0.len()) };
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let span = if event.is_contextual() {
+            Self::current_span()
+        } else {
+            event.parent().map(Id::into_u64).unwrap_or(0)
+        };
+
+        let mut fields = FieldEncoder::default();
+        event.record(&mut fields);
+
+        let record = LogRecord::new(event.metadata(), span, 0, &fields.0);
+        unsafe { ffi::log_event(&record) };
+    }
+
+    fn enter(&self, span: &Id) {
+        SPAN_STACK.with(|stack| stack.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        let id = span.into_u64();
+        SPAN_STACK.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(pos) = stack.iter().rposition(|entered| *entered == id) {
+                stack.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        SPAN_REFS.with(|refs| {
+            if let Some(count) = refs.borrow_mut().get_mut(&span.into_u64()) {
+                *count += 1;
+            }
+        });
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let id = span.into_u64();
+        let closed = SPAN_REFS.with(|refs| {
+            let mut refs = refs.borrow_mut();
+            match refs.get_mut(&id) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    false
+                }
+                Some(_) => {
+                    refs.remove(&id);
+                    true
+                }
+                None => false,
+            }
+        });
+
+        if closed {
+            unsafe { ffi::log_span_close(id) };
+        }
+        closed
+    }
+}
+
+impl LogRecord {
+    fn new(metadata: &Metadata<'_>, span: u64, parent: u64, fields: &[u8]) -> Self {
+        let level = match *metadata.level() {
+            Level::ERROR => 1,
+            Level::WARN => 2,
+            Level::INFO => 3,
+            Level::DEBUG => 4,
+            Level::TRACE => 5,
+        };
+        let target = metadata.target();
+        let name = metadata.name();
+        let file = metadata.file().unwrap_or("");
+
+        Self {
+            level,
+            span,
+            parent,
+            target: target.as_ptr(),
+            target_len: target.len(),
+            name: name.as_ptr(),
+            name_len: name.len(),
+            fields: fields.as_ptr(),
+            fields_len: fields.len(),
+            file: file.as_ptr(),
+            file_len: file.len(),
+            line: metadata.line().unwrap_or(0),
+        }
+    }
+}
+
+impl FieldEncoder {
+    fn push(&mut self, key: &str, value: &str) {
+        self.0.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        self.0.extend_from_slice(key.as_bytes());
+        self.0.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.0.extend_from_slice(value.as_bytes());
+    }
+}
+
+impl Visit for FieldEncoder {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field.name(), value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let mut buf = String::new();
+        let _ = write!(buf, "{:?}", value);
+        self.push(field.name(), &buf);
+    }
+}
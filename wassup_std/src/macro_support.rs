@@ -0,0 +1,233 @@
+//! Items used by the code generated from `wassup_std_macros`, not public api.
+
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::mem;
+use std::task::Context;
+
+pub use std::future::Future;
+pub use std::pin::Pin;
+pub use std::task::Poll;
+
+thread_local! {
+    static SELECT_START: Cell<usize> = const { Cell::new(0) };
+}
+
+pub struct PollFn<F>(F);
+
+pub fn poll_fn<T, F: FnMut(&mut Context<'_>) -> Poll<T>>(f: F) -> PollFn<F> {
+    PollFn(f)
+}
+
+impl<T, F: FnMut(&mut Context<'_>) -> Poll<T>> Future for PollFn<F> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: the closure is never moved out of the pin
+        (unsafe { &mut self.get_unchecked_mut().0 })(cx)
+    }
+}
+
+/// Branch to start polling at for unbiased `select!`, rotates so no branch is always first.
+pub fn select_start(branches: usize) -> usize {
+    SELECT_START.with(|start| {
+        let next = start.get().wrapping_add(1);
+        start.set(next);
+        next % branches
+    })
+}
+
+pub enum MaybeDone<F: Future> {
+    Future(F),
+    Done(F::Output),
+    Gone,
+}
+
+impl<F: Future> MaybeDone<F> {
+    pub fn new(future: F) -> Self {
+        Self::Future(future)
+    }
+
+    /// Polls the inner future if it is still running, returns `true` once an output is stored.
+    pub fn poll_done(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+        // SAFETY: the future is only moved out of after it completed
+        let this = unsafe { self.get_unchecked_mut() };
+        match this {
+            Self::Future(future) => match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                Poll::Ready(output) => {
+                    *this = Self::Done(output);
+                    true
+                }
+                Poll::Pending => false,
+            },
+            Self::Done(_) => true,
+            Self::Gone => panic!("MaybeDone polled after its output was taken"),
+        }
+    }
+
+    pub fn take_output(self: Pin<&mut Self>) -> F::Output {
+        // SAFETY: `Done` doesn't hold a pinned future anymore
+        let this = unsafe { self.get_unchecked_mut() };
+        match mem::replace(this, Self::Gone) {
+            Self::Done(output) => output,
+            _ => panic!("MaybeDone has no output"),
+        }
+    }
+}
+
+pub enum TryMaybeDone<F: Future>
+where
+    F::Output: TryOutput,
+{
+    Future(F),
+    Done(<F::Output as TryOutput>::Ok),
+    Gone,
+}
+
+/// Lets `try_join!` split a future's `Result` output without naming its types.
+pub trait TryOutput {
+    type Ok;
+    type Err;
+
+    fn into_result(self) -> Result<Self::Ok, Self::Err>;
+}
+
+impl<T, E> TryOutput for Result<T, E> {
+    type Ok = T;
+    type Err = E;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+}
+
+impl<F: Future> TryMaybeDone<F>
+where
+    F::Output: TryOutput,
+{
+    pub fn new(future: F) -> Self {
+        Self::Future(future)
+    }
+
+    /// Polls the inner future if it is still running.
+    ///
+    /// Returns `Ready(Ok(()))` once an `Ok` value is stored and `Ready(Err(_))` if the future failed.
+    pub fn poll_done(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), <F::Output as TryOutput>::Err>> {
+        // SAFETY: the future is only moved out of after it completed
+        let this = unsafe { self.get_unchecked_mut() };
+        match this {
+            Self::Future(future) => match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                Poll::Ready(output) => match output.into_result() {
+                    Ok(output) => {
+                        *this = Self::Done(output);
+                        Poll::Ready(Ok(()))
+                    }
+                    Err(err) => {
+                        *this = Self::Gone;
+                        Poll::Ready(Err(err))
+                    }
+                },
+                Poll::Pending => Poll::Pending,
+            },
+            Self::Done(_) => Poll::Ready(Ok(())),
+            Self::Gone => panic!("TryMaybeDone polled after it finished"),
+        }
+    }
+
+    pub fn take_output(self: Pin<&mut Self>) -> <F::Output as TryOutput>::Ok {
+        // SAFETY: `Done` doesn't hold a pinned future anymore
+        let this = unsafe { self.get_unchecked_mut() };
+        match mem::replace(this, Self::Gone) {
+            Self::Done(output) => output,
+            _ => panic!("TryMaybeDone has no output"),
+        }
+    }
+}
+
+/// Output types `#[wassup_std::main]` may return.
+pub trait MainOutput {
+    fn exit_code(self) -> u32;
+}
+
+impl MainOutput for () {
+    fn exit_code(self) -> u32 {
+        0
+    }
+}
+
+impl<E: Debug> MainOutput for Result<(), E> {
+    fn exit_code(self) -> u32 {
+        match self {
+            Ok(()) => 0,
+            Err(err) => {
+                tracing::error!("main returned an error: {:?}", err);
+                1
+            }
+        }
+    }
+}
+
+/// Output types a `#[wassup_std::test]` function may return.
+pub trait TestOutput {
+    fn check(self);
+}
+
+impl TestOutput for () {
+    fn check(self) {}
+}
+
+impl<E: Debug> TestOutput for Result<(), E> {
+    fn check(self) {
+        if let Err(err) = self {
+            panic!("test returned an error: {:?}", err);
+        }
+    }
+}
+
+/// Runs a test as the only root task of a fresh runtime, the runtime shuts down once it passed.
+///
+/// A failing test panics, which traps the instance.
+pub fn run_test<Fut>(test: impl FnOnce() -> Fut)
+where
+    Fut: Future + 'static,
+    Fut::Output: TestOutput,
+{
+    let future = test();
+    drop(crate::spawn(async move {
+        crate::startup_runtime();
+        future.await.check();
+        crate::shutdown_runtime();
+    }));
+}
+
+/// Decodes the arguments the host wrote with `__wassup_alloc` and spawns `handler`, its output
+/// is sent back with `export_complete`.
+#[cfg(any(feature = "serde_json", feature = "bincode", feature = "postcard"))]
+pub fn spawn_export<Args, Fut>(call_id: u64, args: *mut u8, args_len: usize, handler: impl FnOnce(Args) -> Fut)
+where
+    Args: serde::de::DeserializeOwned,
+    Fut: Future + 'static,
+    Fut::Output: serde::Serialize,
+{
+    use crate::ipc::codec::{Codec, DefaultCodec};
+
+    // SAFETY: the host hands over a buffer from `__wassup_alloc` and never touches it again
+    let bytes = unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(args, args_len)) };
+    let args = match DefaultCodec::decode::<Args>(&bytes) {
+        Ok(args) => args,
+        Err(err) => return crate::ffi::complete_export(call_id, Err(err.to_string())),
+    };
+
+    let future = handler(args);
+    drop(crate::spawn(async move {
+        let mut buf = vec![];
+        let result = match DefaultCodec::encode(&future.await, &mut buf) {
+            Ok(()) => Ok(buf),
+            Err(err) => Err(err.to_string()),
+        };
+        crate::ffi::complete_export(call_id, result);
+    }));
+}
@@ -1,10 +1,11 @@
 use crate::{ffi, Yield};
 use std::any::Any;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::future::Future;
 use std::marker::PhantomData;
 use std::mem;
+use std::panic::Location;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
@@ -23,21 +24,29 @@ thread_local! {
             timer_queue: Default::default(),
             tasks: Default::default(),
             poll_again: RefCell::new(Default::default()),
+            current_task: Cell::new(None),
         })
     };
 }
 
-pub fn auto_yield() -> Yield {
-    Yield(unsafe { crate::ffi::yield_rt } != 0)
+/// Snapshots the tasks of the runtime, e.g. to log what keeps it busy
+pub fn dump_tasks() -> Vec<TaskSnapshot> {
+    RUNTIME.with(|rt| rt.dump_tasks())
+}
+
+pub(crate) fn auto_yield() -> Yield {
+    Yield(unsafe { ffi::yield_requested() } != 0)
 }
 
 // Single threaded runtime
-pub struct Runtime {
+pub(crate) struct Runtime {
     timers: RefCell<BTreeMap<(Instant, usize), Waker>>,
     timer_queue: RefCell<VecDeque<TimerOp>>,
 
     tasks: RefCell<HashMap<usize, Rc<TaskHandle>>>,
     poll_again: RefCell<VecDeque<usize>>,
+    /// the task being polled right now
+    current_task: Cell<Option<usize>>,
 }
 
 pub struct JoinHandle<T: 'static> {
@@ -50,6 +59,35 @@ struct TaskHandle {
     future: RefCell<DynFuture>,
     result: RefCell<Option<Box<dyn Any + 'static>>>,
     join_waker: RefCell<Option<Waker>>,
+    name: Option<String>,
+    location: &'static Location<'static>,
+    polls: Cell<u64>,
+    busy: Cell<Duration>,
+    longest_poll: Cell<Duration>,
+}
+
+/// A task as seen by [`dump_tasks`]
+#[derive(Debug, Clone)]
+pub struct TaskSnapshot {
+    pub id: usize,
+    pub name: Option<String>,
+    /// where the task was spawned
+    pub location: &'static Location<'static>,
+    pub polls: u64,
+    /// time spent polling the task
+    pub busy: Duration,
+    pub longest_poll: Duration,
+    pub state: TaskState,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskState {
+    /// being polled right now, or when the guest trapped
+    Running,
+    /// woken and waiting for its turn
+    Scheduled,
+    /// waiting to be woken
+    Idle,
 }
 
 struct TaskWaker {
@@ -61,7 +99,7 @@ enum TimerOp {
     Remove(Instant, usize),
 }
 
-pub struct SleepHandle(Instant, usize);
+pub(crate) struct SleepHandle(Instant, usize);
 
 impl Runtime {
     /// returns a duration in microseconds till the next poll is required
@@ -107,7 +145,15 @@ impl Runtime {
             let future = (*future).as_mut();
 
             // FIXME: Add catch unwind
-            match future.poll(&mut ctx) {
+            self.current_task.set(Some(next));
+            let started = Instant::now();
+            let poll = future.poll(&mut ctx);
+            let busy = started.elapsed();
+            self.current_task.set(None);
+            task.polls.set(task.polls.get() + 1);
+            task.busy.set(task.busy.get() + busy);
+            task.longest_poll.set(task.longest_poll.get().max(busy));
+            match poll {
                 Poll::Ready(result) => {
                     self.tasks.borrow_mut().remove(&next);
 
@@ -118,7 +164,7 @@ impl Runtime {
                     }
                 }
                 Poll::Pending => {
-                    if unsafe { crate::ffi::yield_rt } != 0 {
+                    if unsafe { ffi::yield_requested() } != 0 {
                         // yield from runtime
                         break;
                     }
@@ -135,20 +181,44 @@ impl Runtime {
         let poll_again =
             !self.timer_queue.borrow().is_empty() || !self.poll_again.borrow().is_empty();
 
+        let metrics = ffi::RuntimeMetrics {
+            tasks: self.tasks.borrow().len() as u32,
+            timers: self.timers.borrow().len() as u32,
+            poll_queue: self.poll_again.borrow().len() as u32,
+        };
+        unsafe {
+            ffi::report_metrics(&metrics);
+        }
+
         next_timer_wakeup
             .map(|dur| dur.as_micros() as u64)
             .unwrap_or(if poll_again { 0 } else { u64::MAX })
     }
 
+    /// The id of the task being polled, `None` outside of tasks
+    pub fn current_task(&self) -> Option<usize> {
+        self.current_task.get()
+    }
+
     pub fn shutdown(&self) -> ! {
+        self.exit(0)
+    }
+
+    /// Shuts the runtime down and reports `exit_code` to the host
+    pub fn exit(&self, exit_code: u32) -> ! {
         // drop all tasks for clean exit
         for _ in self.tasks.borrow_mut().drain() {}
         unsafe {
-            ffi::shutdown_rt();
+            ffi::shutdown_rt(exit_code);
         }
     }
 
-    pub fn spawn<R: 'static>(&self, future: impl Future<Output = R> + 'static) -> JoinHandle<R> {
+    pub(crate) fn spawn_at<R: 'static>(
+        &self,
+        name: Option<String>,
+        location: &'static Location<'static>,
+        future: impl Future<Output = R> + 'static,
+    ) -> JoinHandle<R> {
         let task = Box::pin(async move {
             let result = future.await;
             Box::new(result) as Box<dyn Any>
@@ -163,6 +233,11 @@ impl Runtime {
             future: RefCell::new(task),
             result: RefCell::new(None),
             join_waker: RefCell::new(None),
+            name,
+            location,
+            polls: Cell::new(0),
+            busy: Cell::new(Duration::ZERO),
+            longest_poll: Cell::new(Duration::ZERO),
         });
         let join_handle = JoinHandle {
             _phantom: PhantomData,
@@ -175,6 +250,41 @@ impl Runtime {
         join_handle
     }
 
+    /// Snapshots the tasks, sorted by id. Also works after the guest trapped in the middle of a
+    /// poll, where the borrows it held were never released.
+    pub fn dump_tasks(&self) -> Vec<TaskSnapshot> {
+        let scheduled = self
+            .poll_again
+            .try_borrow()
+            .map(|queue| queue.iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let tasks = match self.tasks.try_borrow() {
+            Ok(tasks) => tasks,
+            Err(_) => return vec![],
+        };
+
+        let mut snapshots = tasks
+            .iter()
+            .map(|(&id, task)| TaskSnapshot {
+                id,
+                name: task.name.clone(),
+                location: task.location,
+                polls: task.polls.get(),
+                busy: task.busy.get(),
+                longest_poll: task.longest_poll.get(),
+                state: if self.current_task.get() == Some(id) {
+                    TaskState::Running
+                } else if scheduled.contains(&id) {
+                    TaskState::Scheduled
+                } else {
+                    TaskState::Idle
+                },
+            })
+            .collect::<Vec<_>>();
+        snapshots.sort_by_key(|task| task.id);
+        snapshots
+    }
+
     pub fn schedule_sleep(&self, until: Instant, waker: Waker) -> SleepHandle {
         let id = SLEEP_ID.fetch_add(1, Ordering::Relaxed);
         let op = TimerOp::Insert(until, id, waker);
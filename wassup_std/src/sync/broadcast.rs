@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+pub struct Sender<T>(Rc<RefCell<Shared<T>>>);
+
+pub struct Receiver<T> {
+    shared: Rc<RefCell<Shared<T>>>,
+    /// position of the next message this receiver will see
+    next: u64,
+}
+
+struct Shared<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+    /// position of `buffer[0]`
+    tail: u64,
+    senders: usize,
+    receivers: usize,
+    wakers: Vec<Waker>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RecvError {
+    Closed,
+    /// The receiver fell behind and the given amount of messages got overwritten.
+    Lagged(u64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+struct Recv<'a, T>(&'a mut Receiver<T>);
+
+/// Creates a broadcast channel holding up to `capacity` messages.
+///
+/// Every receiver sees every message sent after it subscribed, receivers that don't keep up
+/// lose the oldest messages and get a [`RecvError::Lagged`].
+///
+/// # Panics
+/// Panics if `capacity` is zero.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel requires capacity > 0");
+    let shared = Rc::new(RefCell::new(Shared {
+        buffer: VecDeque::with_capacity(capacity),
+        capacity,
+        tail: 0,
+        senders: 1,
+        receivers: 1,
+        wakers: vec![],
+    }));
+    let receiver = Receiver {
+        shared: shared.clone(),
+        next: 0,
+    };
+    (Sender(shared), receiver)
+}
+
+impl<T> Shared<T> {
+    /// position the next sent message will get
+    fn head(&self) -> u64 {
+        self.tail + self.buffer.len() as u64
+    }
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to all current receivers and returns how many there are.
+    ///
+    /// Fails if there are no receivers left.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut shared = self.0.borrow_mut();
+        if shared.receivers == 0 {
+            return Err(SendError(value));
+        }
+
+        if shared.buffer.len() == shared.capacity {
+            shared.buffer.pop_front();
+            shared.tail += 1;
+        }
+        shared.buffer.push_back(value);
+
+        for waker in shared.wakers.drain(..) {
+            waker.wake();
+        }
+
+        Ok(shared.receivers)
+    }
+
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut shared = self.0.borrow_mut();
+        shared.receivers += 1;
+        Receiver {
+            shared: self.0.clone(),
+            next: shared.head(),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.0.borrow().receivers
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        Recv(self).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let shared = self.shared.borrow();
+
+        if self.next < shared.tail {
+            let missed = shared.tail - self.next;
+            self.next = shared.tail;
+            return Err(TryRecvError::Lagged(missed));
+        }
+
+        if self.next == shared.head() {
+            return if shared.senders == 0 {
+                Err(TryRecvError::Closed)
+            } else {
+                Err(TryRecvError::Empty)
+            };
+        }
+
+        let value = shared.buffer[(self.next - shared.tail) as usize].clone();
+        self.next += 1;
+        Ok(value)
+    }
+
+    pub fn resubscribe(&self) -> Self {
+        let mut shared = self.shared.borrow_mut();
+        shared.receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+            next: shared.head(),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.borrow_mut().senders += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.0.borrow_mut();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            for waker in shared.wakers.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.borrow_mut().receivers -= 1;
+    }
+}
+
+impl<'a, T: Clone> Future for Recv<'a, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.0.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Lagged(n)) => Poll::Ready(Err(RecvError::Lagged(n))),
+            Err(TryRecvError::Empty) => {
+                super::register_waker(&mut self.0.shared.borrow_mut().wakers, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl<T: fmt::Debug> Error for SendError<T> {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Closed => write!(f, "channel closed"),
+            RecvError::Lagged(n) => write!(f, "channel lagged by {}", n),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel empty"),
+            TryRecvError::Closed => write!(f, "channel closed"),
+            TryRecvError::Lagged(n) => write!(f, "channel lagged by {}", n),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
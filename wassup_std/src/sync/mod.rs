@@ -0,0 +1,32 @@
+//! Communication and synchronization primitives for the single threaded runtime.
+//!
+//! None of these types are `Send` or `Sync`, they are meant to be shared between tasks spawned
+//! on the same runtime and wake each other through the runtime's wakers.
+
+use std::task::Waker;
+
+pub mod broadcast;
+pub mod mpsc;
+mod mutex;
+mod notify;
+pub mod oneshot;
+mod rwlock;
+mod semaphore;
+pub mod watch;
+
+pub use mutex::{Mutex, MutexGuard, OwnedMutexGuard, TryLockError};
+pub use notify::{Notified, Notify};
+pub use rwlock::{
+    OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+pub use semaphore::{
+    AcquireError, OwnedSemaphorePermit, Semaphore, SemaphorePermit, TryAcquireError,
+};
+
+/// Adds `waker` unless one waking the same task is there already, a future that keeps getting
+/// polled without being woken, e.g. in `select!`, would pile up clones otherwise
+fn register_waker(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
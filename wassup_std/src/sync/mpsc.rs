@@ -0,0 +1,298 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+pub struct Sender<T>(Rc<RefCell<Chan<T>>>);
+pub struct Receiver<T>(Rc<RefCell<Chan<T>>>);
+
+pub struct UnboundedSender<T>(Rc<RefCell<Chan<T>>>);
+pub struct UnboundedReceiver<T>(Receiver<T>);
+
+struct Chan<T> {
+    queue: VecDeque<T>,
+    /// `None` for unbounded channels
+    capacity: Option<usize>,
+    senders: usize,
+    closed: bool,
+    recv_waker: Option<Waker>,
+    send_wakers: Vec<Waker>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+struct Capacity<'a, T>(&'a RefCell<Chan<T>>);
+struct Recv<'a, T>(&'a RefCell<Chan<T>>);
+
+/// Creates a bounded channel, `send` waits while `buffer` messages are queued.
+///
+/// # Panics
+/// Panics if `buffer` is zero.
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(buffer > 0, "mpsc bounded channel requires buffer > 0");
+    let chan = Chan::new(Some(buffer));
+    (Sender(chan.clone()), Receiver(chan))
+}
+
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    let chan = Chan::new(None);
+    (UnboundedSender(chan.clone()), UnboundedReceiver(Receiver(chan)))
+}
+
+impl<T> Chan<T> {
+    fn new(capacity: Option<usize>) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            queue: VecDeque::with_capacity(capacity.unwrap_or(16)),
+            capacity,
+            senders: 1,
+            closed: false,
+            recv_waker: None,
+            send_wakers: vec![],
+        }))
+    }
+
+    fn is_full(&self) -> bool {
+        matches!(self.capacity, Some(cap) if self.queue.len() >= cap)
+    }
+
+    fn push(&mut self, value: T) {
+        self.queue.push_back(value);
+        if let Some(waker) = self.recv_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let value = self.queue.pop_front()?;
+        for waker in self.send_wakers.drain(..) {
+            waker.wake();
+        }
+        Some(value)
+    }
+
+    fn try_send(&mut self, value: T) -> Result<(), TrySendError<T>> {
+        if self.closed {
+            Err(TrySendError::Closed(value))
+        } else if self.is_full() {
+            Err(TrySendError::Full(value))
+        } else {
+            self.push(value);
+            Ok(())
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        match self.pop() {
+            Some(value) => Ok(value),
+            None if self.senders == 0 || self.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        for waker in self.send_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn add_sender(&mut self) {
+        self.senders += 1;
+    }
+
+    fn remove_sender(&mut self) {
+        self.senders -= 1;
+        if self.senders == 0 {
+            if let Some(waker) = self.recv_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Waits until there is capacity in the channel and sends `value`.
+    ///
+    /// Fails if the receiver has been dropped or closed.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if !Capacity(&self.0).await {
+            return Err(SendError(value));
+        }
+        self.0.borrow_mut().push(value);
+        Ok(())
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.0.borrow_mut().try_send(value)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.borrow().closed
+    }
+
+    pub fn capacity(&self) -> usize {
+        let chan = self.0.borrow();
+        chan.capacity.unwrap_or(usize::MAX) - chan.queue.len()
+    }
+}
+
+impl<T> UnboundedSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.0.borrow_mut().try_send(value).map_err(|err| match err {
+            TrySendError::Full(value) | TrySendError::Closed(value) => SendError(value),
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.borrow().closed
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next message, returns `None` once all senders are gone and the buffer is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        Recv(&self.0).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.0.borrow_mut().try_recv()
+    }
+
+    /// Closes the receiving half, already buffered messages can still be received.
+    pub fn close(&mut self) {
+        self.0.borrow_mut().close();
+    }
+}
+
+impl<T> UnboundedReceiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        self.0.recv().await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.0.try_recv()
+    }
+
+    pub fn close(&mut self) {
+        self.0.close()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.borrow_mut().add_sender();
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        self.0.borrow_mut().add_sender();
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.borrow_mut().remove_sender();
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.0.borrow_mut().remove_sender();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut chan = self.0.borrow_mut();
+        chan.close();
+        // drop buffered messages now instead of when the last sender goes away,
+        // outside of the borrow as they might contain senders of this channel
+        let queue = mem::take(&mut chan.queue);
+        drop(chan);
+        drop(queue);
+    }
+}
+
+impl<'a, T> Future for Capacity<'a, T> {
+    /// `false` if the channel got closed
+    type Output = bool;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut chan = self.0.borrow_mut();
+        if chan.closed {
+            Poll::Ready(false)
+        } else if !chan.is_full() {
+            Poll::Ready(true)
+        } else {
+            super::register_waker(&mut chan.send_wakers, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a, T> Future for Recv<'a, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut chan = self.0.borrow_mut();
+        match chan.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                chan.recv_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl<T: fmt::Debug> Error for SendError<T> {}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "no available capacity"),
+            TrySendError::Closed(_) => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl<T: fmt::Debug> Error for TrySendError<T> {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel empty"),
+            TryRecvError::Disconnected => write!(f, "channel disconnected"),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
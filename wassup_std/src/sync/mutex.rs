@@ -0,0 +1,138 @@
+use crate::sync::semaphore::{Semaphore, SemaphorePermit};
+use std::cell::UnsafeCell;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// An async mutex, its guard may be held across `.await` points.
+pub struct Mutex<T: ?Sized> {
+    sem: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    _permit: SemaphorePermit<'a>,
+    lock: &'a Mutex<T>,
+}
+
+/// Releases its permit by hand on drop, since the semaphore lives inside the mutex.
+pub struct OwnedMutexGuard<T: ?Sized> {
+    lock: Rc<Mutex<T>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TryLockError;
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            sem: Semaphore::new(1),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        // the semaphore is never closed, so acquiring can't fail
+        let permit = self.sem.acquire().await.unwrap();
+        MutexGuard {
+            _permit: permit,
+            lock: self,
+        }
+    }
+
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, TryLockError> {
+        let permit = self.sem.try_acquire().map_err(|_| TryLockError)?;
+        Ok(MutexGuard {
+            _permit: permit,
+            lock: self,
+        })
+    }
+
+    pub async fn lock_owned(self: Rc<Self>) -> OwnedMutexGuard<T> {
+        self.sem.acquire().await.unwrap().forget();
+        OwnedMutexGuard { lock: self }
+    }
+
+    pub fn try_lock_owned(self: Rc<Self>) -> Result<OwnedMutexGuard<T>, TryLockError> {
+        self.sem.try_acquire().map_err(|_| TryLockError)?.forget();
+        Ok(OwnedMutexGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the semaphore permit guarantees exclusive access
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the semaphore permit guarantees exclusive access
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedMutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the semaphore permit guarantees exclusive access
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for OwnedMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the semaphore permit guarantees exclusive access
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedMutexGuard<T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Ok(guard) => f.debug_struct("Mutex").field("value", &&*guard).finish(),
+            Err(_) => f.debug_struct("Mutex").field("value", &"<locked>").finish(),
+        }
+    }
+}
+
+impl fmt::Display for TryLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock is already held")
+    }
+}
+
+impl Error for TryLockError {}
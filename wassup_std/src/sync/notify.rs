@@ -0,0 +1,135 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// Notifies a single task or all currently waiting tasks.
+///
+/// `notify_one` without a waiting task stores a single permit which the next `notified()`
+/// consumes right away.
+#[derive(Default)]
+pub struct Notify {
+    state: RefCell<State>,
+}
+
+#[derive(Default)]
+struct State {
+    permit: bool,
+    waiters: VecDeque<Rc<Waiter>>,
+}
+
+#[derive(Default)]
+struct Waiter {
+    notified: Cell<Option<Notification>>,
+    waker: RefCell<Option<Waker>>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Notification {
+    One,
+    All,
+}
+
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Rc<Waiter>>,
+    done: bool,
+}
+
+impl Notify {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+            done: false,
+        }
+    }
+
+    pub fn notify_one(&self) {
+        let mut state = self.state.borrow_mut();
+        match state.waiters.pop_front() {
+            Some(waiter) => waiter.notify(Notification::One),
+            None => state.permit = true,
+        }
+    }
+
+    /// Wakes every task currently waiting, does not store a permit.
+    pub fn notify_waiters(&self) {
+        for waiter in self.state.borrow_mut().waiters.drain(..) {
+            waiter.notify(Notification::All);
+        }
+    }
+}
+
+impl Waiter {
+    fn notify(&self, notification: Notification) {
+        self.notified.set(Some(notification));
+        if let Some(waker) = self.waker.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<'a> Future for Notified<'a> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.done {
+            return Poll::Ready(());
+        }
+
+        let waiter = match &self.waiter {
+            Some(waiter) => waiter.clone(),
+            None => {
+                let mut state = self.notify.state.borrow_mut();
+                if state.permit {
+                    state.permit = false;
+                    drop(state);
+                    self.done = true;
+                    return Poll::Ready(());
+                }
+
+                let waiter = Rc::new(Waiter::default());
+                state.waiters.push_back(waiter.clone());
+                drop(state);
+                self.waiter = Some(waiter.clone());
+                waiter
+            }
+        };
+
+        if waiter.notified.get().is_some() {
+            self.done = true;
+            Poll::Ready(())
+        } else {
+            waiter.waker.borrow_mut().replace(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a> Drop for Notified<'a> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+
+        match waiter.notified.get() {
+            // hand a `notify_one` that was never observed to the next waiter
+            Some(Notification::One) if !self.done => self.notify.notify_one(),
+            Some(_) => {}
+            None => self
+                .notify
+                .state
+                .borrow_mut()
+                .waiters
+                .retain(|other| !Rc::ptr_eq(other, &waiter)),
+        }
+    }
+}
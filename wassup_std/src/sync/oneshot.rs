@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+pub struct Sender<T>(Rc<RefCell<Inner<T>>>);
+pub struct Receiver<T>(Rc<RefCell<Inner<T>>>);
+
+struct Inner<T> {
+    value: Option<T>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+    recv_waker: Option<Waker>,
+    closed_waker: Option<Waker>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+struct Closed<'a, T>(&'a RefCell<Inner<T>>);
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Rc::new(RefCell::new(Inner {
+        value: None,
+        sender_dropped: false,
+        receiver_dropped: false,
+        recv_waker: None,
+        closed_waker: None,
+    }));
+    (Sender(inner.clone()), Receiver(inner))
+}
+
+impl<T> Sender<T> {
+    /// Sends `value` to the receiver, giving it back if the receiver is gone.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut inner = self.0.borrow_mut();
+        if inner.receiver_dropped {
+            return Err(value);
+        }
+        inner.value = Some(value);
+        Ok(())
+        // the receiver gets woken when `self` is dropped
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.0.borrow().receiver_dropped
+    }
+
+    /// Waits until the receiver is dropped or closed.
+    pub async fn closed(&mut self) {
+        Closed(&self.0).await
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = self.0.borrow_mut();
+        match inner.value.take() {
+            Some(value) => Ok(value),
+            None if inner.sender_dropped => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Prevents the sender from sending a value, an already sent value can still be received.
+    pub fn close(&mut self) {
+        let mut inner = self.0.borrow_mut();
+        inner.receiver_dropped = true;
+        if let Some(waker) = inner.closed_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.0.borrow_mut();
+        inner.sender_dropped = true;
+        if let Some(waker) = inner.recv_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+        let value = self.0.borrow_mut().value.take();
+        drop(value);
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.0.borrow_mut();
+        if let Some(value) = inner.value.take() {
+            Poll::Ready(Ok(value))
+        } else if inner.sender_dropped {
+            Poll::Ready(Err(RecvError))
+        } else {
+            inner.recv_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a, T> Future for Closed<'a, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.0.borrow_mut();
+        if inner.receiver_dropped {
+            Poll::Ready(())
+        } else {
+            inner.closed_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl Error for RecvError {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel empty"),
+            TryRecvError::Closed => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
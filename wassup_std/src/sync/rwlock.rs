@@ -0,0 +1,183 @@
+use crate::sync::mutex::TryLockError;
+use crate::sync::semaphore::{Semaphore, SemaphorePermit};
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// Readers take one permit each, a writer takes all of them.
+const MAX_READS: usize = u32::MAX as usize >> 3;
+
+/// An async reader-writer lock, its guards may be held across `.await` points.
+pub struct RwLock<T: ?Sized> {
+    sem: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    _permit: SemaphorePermit<'a>,
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    _permit: SemaphorePermit<'a>,
+    lock: &'a RwLock<T>,
+}
+
+/// Releases its permit by hand on drop, since the semaphore lives inside the lock.
+pub struct OwnedRwLockReadGuard<T: ?Sized> {
+    lock: Rc<RwLock<T>>,
+}
+
+/// Releases its permits by hand on drop, since the semaphore lives inside the lock.
+pub struct OwnedRwLockWriteGuard<T: ?Sized> {
+    lock: Rc<RwLock<T>>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            sem: Semaphore::new(MAX_READS),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        // the semaphore is never closed, so acquiring can't fail
+        let permit = self.sem.acquire().await.unwrap();
+        RwLockReadGuard {
+            _permit: permit,
+            lock: self,
+        }
+    }
+
+    pub fn try_read(&self) -> Result<RwLockReadGuard<'_, T>, TryLockError> {
+        let permit = self.sem.try_acquire().map_err(|_| TryLockError)?;
+        Ok(RwLockReadGuard {
+            _permit: permit,
+            lock: self,
+        })
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let permit = self.sem.acquire_many(MAX_READS).await.unwrap();
+        RwLockWriteGuard {
+            _permit: permit,
+            lock: self,
+        }
+    }
+
+    pub fn try_write(&self) -> Result<RwLockWriteGuard<'_, T>, TryLockError> {
+        let permit = self
+            .sem
+            .try_acquire_many(MAX_READS)
+            .map_err(|_| TryLockError)?;
+        Ok(RwLockWriteGuard {
+            _permit: permit,
+            lock: self,
+        })
+    }
+
+    pub async fn read_owned(self: Rc<Self>) -> OwnedRwLockReadGuard<T> {
+        self.sem.acquire().await.unwrap().forget();
+        OwnedRwLockReadGuard { lock: self }
+    }
+
+    pub async fn write_owned(self: Rc<Self>) -> OwnedRwLockWriteGuard<T> {
+        self.sem.acquire_many(MAX_READS).await.unwrap().forget();
+        OwnedRwLockWriteGuard { lock: self }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: no writer can exist while a read permit is held
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the write guard holds all permits
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the write guard holds all permits
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedRwLockReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: no writer can exist while a read permit is held
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedRwLockWriteGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the write guard holds all permits
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for OwnedRwLockWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: the write guard holds all permits
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedRwLockReadGuard<T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(1);
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedRwLockWriteGuard<T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(MAX_READS);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_read() {
+            Ok(guard) => f.debug_struct("RwLock").field("value", &&*guard).finish(),
+            Err(_) => f.debug_struct("RwLock").field("value", &"<locked>").finish(),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+pub struct Semaphore {
+    state: RefCell<State>,
+}
+
+struct State {
+    permits: usize,
+    closed: bool,
+    /// permits are handed out in this order, so a large acquire isn't starved by small ones
+    waiters: VecDeque<Rc<Waiter>>,
+}
+
+struct Waiter {
+    permits: usize,
+    /// the permits were taken for this waiter, it only has to pick them up
+    granted: Cell<bool>,
+    waker: RefCell<Waker>,
+}
+
+pub struct SemaphorePermit<'a> {
+    sem: &'a Semaphore,
+    permits: usize,
+}
+
+pub struct OwnedSemaphorePermit {
+    sem: Rc<Semaphore>,
+    permits: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AcquireError;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TryAcquireError {
+    Closed,
+    NoPermits,
+}
+
+struct Acquire<'a> {
+    sem: &'a Semaphore,
+    permits: usize,
+    /// set once the acquire had to queue
+    waiter: Option<Rc<Waiter>>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: RefCell::new(State {
+                permits,
+                closed: false,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.borrow().permits
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.state.borrow_mut();
+        state.permits += n;
+        state.grant();
+    }
+
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, AcquireError> {
+        self.acquire_many(1).await
+    }
+
+    pub async fn acquire_many(&self, n: usize) -> Result<SemaphorePermit<'_>, AcquireError> {
+        Acquire {
+            sem: self,
+            permits: n,
+            waiter: None,
+        }
+        .await?;
+        Ok(SemaphorePermit {
+            sem: self,
+            permits: n,
+        })
+    }
+
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_take(n)?;
+        Ok(SemaphorePermit {
+            sem: self,
+            permits: n,
+        })
+    }
+
+    pub async fn acquire_owned(self: Rc<Self>) -> Result<OwnedSemaphorePermit, AcquireError> {
+        self.acquire_many_owned(1).await
+    }
+
+    pub async fn acquire_many_owned(
+        self: Rc<Self>,
+        n: usize,
+    ) -> Result<OwnedSemaphorePermit, AcquireError> {
+        Acquire {
+            sem: &self,
+            permits: n,
+            waiter: None,
+        }
+        .await?;
+        Ok(OwnedSemaphorePermit {
+            sem: self,
+            permits: n,
+        })
+    }
+
+    pub fn try_acquire_owned(self: Rc<Self>) -> Result<OwnedSemaphorePermit, TryAcquireError> {
+        self.try_acquire_many_owned(1)
+    }
+
+    pub fn try_acquire_many_owned(
+        self: Rc<Self>,
+        n: usize,
+    ) -> Result<OwnedSemaphorePermit, TryAcquireError> {
+        self.try_take(n)?;
+        Ok(OwnedSemaphorePermit {
+            sem: self,
+            permits: n,
+        })
+    }
+
+    /// Closes the semaphore, all pending and future acquires fail.
+    ///
+    /// Permits that are already handed out stay valid.
+    pub fn close(&self) {
+        let mut state = self.state.borrow_mut();
+        state.closed = true;
+        for waiter in state.waiters.drain(..) {
+            waiter.waker.borrow().wake_by_ref();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.borrow().closed
+    }
+
+    fn try_take(&self, n: usize) -> Result<(), TryAcquireError> {
+        let mut state = self.state.borrow_mut();
+        if state.closed {
+            Err(TryAcquireError::Closed)
+        } else if state.permits < n || !state.waiters.is_empty() {
+            Err(TryAcquireError::NoPermits)
+        } else {
+            state.permits -= n;
+            Ok(())
+        }
+    }
+}
+
+impl State {
+    /// Hands permits to the waiters in queue order, until the first one that doesn't fit
+    fn grant(&mut self) {
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.permits > self.permits {
+                break;
+            }
+            self.permits -= waiter.permits;
+            waiter.granted.set(true);
+            waiter.waker.borrow().wake_by_ref();
+            self.waiters.pop_front();
+        }
+    }
+}
+
+impl<'a> SemaphorePermit<'a> {
+    /// Drops the permit without giving it back to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+}
+
+impl OwnedSemaphorePermit {
+    /// Drops the permit without giving it back to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+
+    pub fn semaphore(&self) -> &Rc<Semaphore> {
+        &self.sem
+    }
+}
+
+impl<'a> Drop for SemaphorePermit<'a> {
+    fn drop(&mut self) {
+        self.sem.add_permits(mem::take(&mut self.permits));
+    }
+}
+
+impl Drop for OwnedSemaphorePermit {
+    fn drop(&mut self) {
+        self.sem.add_permits(mem::take(&mut self.permits));
+    }
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Result<(), AcquireError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(waiter) = &self.waiter {
+            if waiter.granted.get() {
+                self.waiter = None;
+                return Poll::Ready(Ok(()));
+            }
+            if self.sem.is_closed() {
+                self.waiter = None;
+                return Poll::Ready(Err(AcquireError));
+            }
+            if !waiter.waker.borrow().will_wake(cx.waker()) {
+                *waiter.waker.borrow_mut() = cx.waker().clone();
+            }
+            return Poll::Pending;
+        }
+
+        match self.sem.try_take(self.permits) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(TryAcquireError::Closed) => Poll::Ready(Err(AcquireError)),
+            Err(TryAcquireError::NoPermits) => {
+                let waiter = Rc::new(Waiter {
+                    permits: self.permits,
+                    granted: Cell::new(false),
+                    waker: RefCell::new(cx.waker().clone()),
+                });
+                self.sem.state.borrow_mut().waiters.push_back(waiter.clone());
+                self.waiter = Some(waiter);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        if waiter.granted.get() {
+            // cancelled after the permits were taken for it
+            self.sem.add_permits(self.permits);
+        } else {
+            // leaving the front of the queue may let the ones behind it through
+            let mut state = self.sem.state.borrow_mut();
+            state.waiters.retain(|queued| !Rc::ptr_eq(queued, &waiter));
+            state.grant();
+        }
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("Semaphore")
+            .field("permits", &state.permits)
+            .field("closed", &state.closed)
+            .finish()
+    }
+}
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "semaphore closed")
+    }
+}
+
+impl Error for AcquireError {}
+
+impl fmt::Display for TryAcquireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryAcquireError::Closed => write!(f, "semaphore closed"),
+            TryAcquireError::NoPermits => write!(f, "no permits available"),
+        }
+    }
+}
+
+impl Error for TryAcquireError {}
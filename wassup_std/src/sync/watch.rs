@@ -0,0 +1,199 @@
+use std::cell::{Ref, RefCell};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+pub struct Sender<T>(Rc<Shared<T>>);
+
+pub struct Receiver<T> {
+    shared: Rc<Shared<T>>,
+    /// version of the value this receiver has last seen
+    seen: u64,
+}
+
+struct Shared<T> {
+    value: RefCell<T>,
+    state: RefCell<State>,
+}
+
+struct State {
+    version: u64,
+    sender_dropped: bool,
+    receivers: usize,
+    wakers: Vec<Waker>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RecvError;
+
+struct Changed<'a, T>(&'a Receiver<T>);
+
+/// Creates a channel that only keeps the most recent value, starting with `init`.
+pub fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Rc::new(Shared {
+        value: RefCell::new(init),
+        state: RefCell::new(State {
+            version: 0,
+            sender_dropped: false,
+            receivers: 1,
+            wakers: vec![],
+        }),
+    });
+    let receiver = Receiver {
+        shared: shared.clone(),
+        seen: 0,
+    };
+    (Sender(shared), receiver)
+}
+
+impl<T> Shared<T> {
+    fn notify(&self) {
+        let mut state = self.state.borrow_mut();
+        state.version += 1;
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Replaces the current value and notifies all receivers.
+    ///
+    /// Fails if there are no receivers left.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.0.state.borrow().receivers == 0 {
+            return Err(SendError(value));
+        }
+        self.send_replace(value);
+        Ok(())
+    }
+
+    /// Replaces the current value even if there are no receivers and returns the old one.
+    pub fn send_replace(&self, value: T) -> T {
+        let old = mem::replace(&mut *self.0.value.borrow_mut(), value);
+        self.0.notify();
+        old
+    }
+
+    /// Modifies the current value in place and notifies all receivers.
+    pub fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        modify(&mut *self.0.value.borrow_mut());
+        self.0.notify();
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.0.value.borrow()
+    }
+
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.0.state.borrow_mut();
+        state.receivers += 1;
+        Receiver {
+            shared: self.0.clone(),
+            seen: state.version,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.0.state.borrow().receivers
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.receiver_count() == 0
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Borrows the current value without marking it as seen.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.shared.value.borrow()
+    }
+
+    /// Borrows the current value and marks it as seen.
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        self.seen = self.shared.state.borrow().version;
+        self.shared.value.borrow()
+    }
+
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        let state = self.shared.state.borrow();
+        if state.sender_dropped {
+            return Err(RecvError);
+        }
+        Ok(state.version != self.seen)
+    }
+
+    /// Waits for a value that has not been seen yet and marks it as seen.
+    ///
+    /// Fails once the sender is dropped.
+    pub async fn changed(&mut self) -> Result<(), RecvError> {
+        Changed(self).await?;
+        self.seen = self.shared.state.borrow().version;
+        Ok(())
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.borrow_mut().receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.borrow_mut();
+        state.sender_dropped = true;
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.borrow_mut().receivers -= 1;
+    }
+}
+
+impl<'a, T> Future for Changed<'a, T> {
+    type Output = Result<(), RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.shared.state.borrow_mut();
+        if state.version != self.0.seen {
+            Poll::Ready(Ok(()))
+        } else if state.sender_dropped {
+            Poll::Ready(Err(RecvError))
+        } else {
+            super::register_waker(&mut state.wakers, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl<T: fmt::Debug> Error for SendError<T> {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl Error for RecvError {}
@@ -0,0 +1,61 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{FnArg, ItemFn, Pat};
+
+/// The handler stays as written, an entry point named `__wassup_export:<name>` decodes the
+/// arguments and spawns it.
+pub fn expand_export(input: ItemFn) -> syn::Result<TokenStream> {
+    let sig = &input.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig.fn_token,
+            "`#[wassup_std::export]` functions must be async",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&sig.generics, "exported functions can't be generic"));
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(syn::Error::new_spanned(variadic, "exported functions can't be variadic"));
+    }
+
+    let mut idents = vec![];
+    let mut types = vec![];
+    for input in &sig.inputs {
+        let arg = match input {
+            FnArg::Typed(arg) => arg,
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(receiver, "exported functions can't take `self`"))
+            }
+        };
+        if !matches!(&*arg.pat, Pat::Ident(_) | Pat::Wild(_)) {
+            return Err(syn::Error::new_spanned(&arg.pat, "expected an argument name"));
+        }
+        idents.push(format_ident!("__wassup_arg{}", idents.len()));
+        types.push(&arg.ty);
+    }
+
+    // a single argument is decoded as is, others as a tuple
+    let (args_pat, args_ty) = match (&idents[..], &types[..]) {
+        ([ident], [ty]) => (quote!(#ident), quote!(#ty)),
+        _ => (quote!((#(#idents,)*)), quote!((#(#types,)*))),
+    };
+
+    let name = &sig.ident;
+    let entry = format_ident!("__wassup_export_{}", name);
+
+    Ok(quote! {
+        #input
+
+        #[doc(hidden)]
+        #[export_name = concat!("__wassup_export:", stringify!(#name))]
+        pub extern "C" fn #entry(call_id: u64, args: *mut u8, args_len: usize) {
+            ::wassup_std::macro_support::spawn_export(
+                call_id,
+                args,
+                args_len,
+                |#args_pat: #args_ty| #name(#(#idents),*),
+            );
+        }
+    })
+}
@@ -0,0 +1,148 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{FnArg, ForeignItem, ForeignItemFn, Ident, ItemForeignMod, LitStr, Pat, Token, Type};
+
+/// `module = "name"`
+pub struct ImportArgs {
+    module: LitStr,
+}
+
+impl Parse for ImportArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key = input.parse::<Ident>()?;
+        if key != "module" {
+            return Err(syn::Error::new_spanned(key, "expected `module = \"...\"`"));
+        }
+        input.parse::<Token![=]>()?;
+        let module = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+
+        Ok(Self { module })
+    }
+}
+
+/// How a wrapper argument is passed to the host
+enum Lowering {
+    /// passed as is
+    Value,
+    /// `&str` and `&[u8]`, passed as pointer and length
+    Slice,
+    /// `&mut [u8]`, passed as pointer and length for the host to write into
+    SliceMut,
+}
+
+fn lowering(ty: &Type) -> syn::Result<Lowering> {
+    let reference = match ty {
+        Type::Reference(reference) => reference,
+        _ => return Ok(Lowering::Value),
+    };
+    let is_bytes = matches!(&*reference.elem, Type::Slice(slice) if is_ident(&slice.elem, "u8"));
+    match (reference.mutability.is_some(), is_bytes || is_ident(&reference.elem, "str")) {
+        (false, true) => Ok(Lowering::Slice),
+        (true, true) if is_bytes => Ok(Lowering::SliceMut),
+        _ => Err(syn::Error::new_spanned(
+            ty,
+            "imports only take `&str`, `&[u8]`, `&mut [u8]` or wasm value types",
+        )),
+    }
+}
+
+fn is_ident(ty: &Type, name: &str) -> bool {
+    matches!(ty, Type::Path(path) if path.qself.is_none() && path.path.is_ident(name))
+}
+
+pub fn expand_import(args: ImportArgs, input: ItemForeignMod) -> syn::Result<TokenStream> {
+    let module = &args.module;
+
+    let mut raw_fns = vec![];
+    let mut wrappers = vec![];
+    for item in &input.items {
+        let func = match item {
+            ForeignItem::Fn(func) => func,
+            item => return Err(syn::Error::new(item.span(), "only functions can be imported")),
+        };
+        let (raw_fn, wrapper) = expand_fn(func)?;
+        raw_fns.push(raw_fn);
+        wrappers.push(wrapper);
+    }
+
+    Ok(quote! {
+        #[link(wasm_import_module = #module)]
+        extern "C" {
+            #(#raw_fns)*
+        }
+
+        #(#wrappers)*
+    })
+}
+
+fn expand_fn(func: &ForeignItemFn) -> syn::Result<(TokenStream, TokenStream)> {
+    let sig = &func.sig;
+    if let Some(variadic) = &sig.variadic {
+        return Err(syn::Error::new_spanned(variadic, "imports can't be variadic"));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&sig.generics, "imports can't be generic"));
+    }
+
+    let name = &sig.ident;
+    let raw_name = format_ident!("__wassup_import_{}", name);
+    let link_name = name.to_string();
+
+    let mut raw_params = vec![];
+    let mut wrapper_params = vec![];
+    let mut call_args = vec![];
+    for input in &sig.inputs {
+        let arg = match input {
+            FnArg::Typed(arg) => arg,
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(receiver, "imports can't take `self`"))
+            }
+        };
+        let ident = match &*arg.pat {
+            Pat::Ident(pat) => &pat.ident,
+            pat => return Err(syn::Error::new_spanned(pat, "expected an argument name")),
+        };
+        let ty = &arg.ty;
+        wrapper_params.push(quote!(#ident: #ty));
+
+        match lowering(ty)? {
+            Lowering::Value => {
+                raw_params.push(quote!(#ident: #ty));
+                call_args.push(quote!(#ident));
+            }
+            Lowering::Slice => {
+                let ptr = format_ident!("{}_ptr", ident);
+                let len = format_ident!("{}_len", ident);
+                raw_params.push(quote!(#ptr: *const u8, #len: usize));
+                call_args.push(quote!(#ident.as_ptr(), #ident.len()));
+            }
+            Lowering::SliceMut => {
+                let ptr = format_ident!("{}_ptr", ident);
+                let len = format_ident!("{}_len", ident);
+                raw_params.push(quote!(#ptr: *mut u8, #len: usize));
+                call_args.push(quote!(#ident.as_mut_ptr(), #ident.len()));
+            }
+        }
+    }
+
+    let attrs = &func.attrs;
+    let vis = &func.vis;
+    let output = &sig.output;
+
+    let raw_fn = quote! {
+        #[link_name = #link_name]
+        fn #raw_name(#(#raw_params),*) #output;
+    };
+    let wrapper = quote! {
+        #(#attrs)*
+        #vis fn #name(#(#wrapper_params),*) #output {
+            // SAFETY: the host is trusted to only touch the memory it was handed
+            unsafe { #raw_name(#(#call_args),*) }
+        }
+    };
+
+    Ok((raw_fn, wrapper))
+}
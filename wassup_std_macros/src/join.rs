@@ -0,0 +1,75 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Expr, Token};
+
+pub struct Join {
+    futures: Punctuated<Expr, Token![,]>,
+}
+
+impl Parse for Join {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(Self {
+            futures: Punctuated::parse_terminated(input)?,
+        })
+    }
+}
+
+pub fn expand_join(join: Join) -> TokenStream {
+    let futures = join.futures.iter().collect::<Vec<_>>();
+    let idents = (0..futures.len())
+        .map(|i| format_ident!("__wassup_fut{}", i))
+        .collect::<Vec<_>>();
+
+    quote! {{
+        #(
+            let mut #idents = ::wassup_std::macro_support::MaybeDone::new(#futures);
+            // SAFETY: shadowed right away, so the future can never be moved again
+            let mut #idents = unsafe { ::wassup_std::macro_support::Pin::new_unchecked(&mut #idents) };
+        )*
+        ::wassup_std::macro_support::poll_fn(|cx| {
+            let mut done = true;
+            #(
+                done &= #idents.as_mut().poll_done(cx);
+            )*
+            if done {
+                ::wassup_std::macro_support::Poll::Ready((#(#idents.as_mut().take_output(),)*))
+            } else {
+                ::wassup_std::macro_support::Poll::Pending
+            }
+        }).await
+    }}
+}
+
+pub fn expand_try_join(join: Join) -> TokenStream {
+    let futures = join.futures.iter().collect::<Vec<_>>();
+    let idents = (0..futures.len())
+        .map(|i| format_ident!("__wassup_fut{}", i))
+        .collect::<Vec<_>>();
+
+    quote! {{
+        #(
+            let mut #idents = ::wassup_std::macro_support::TryMaybeDone::new(#futures);
+            // SAFETY: shadowed right away, so the future can never be moved again
+            let mut #idents = unsafe { ::wassup_std::macro_support::Pin::new_unchecked(&mut #idents) };
+        )*
+        ::wassup_std::macro_support::poll_fn(|cx| {
+            let mut done = true;
+            #(
+                match #idents.as_mut().poll_done(cx) {
+                    ::wassup_std::macro_support::Poll::Ready(Ok(())) => {}
+                    ::wassup_std::macro_support::Poll::Ready(Err(err)) => {
+                        return ::wassup_std::macro_support::Poll::Ready(Err(err));
+                    }
+                    ::wassup_std::macro_support::Poll::Pending => done = false,
+                }
+            )*
+            if done {
+                ::wassup_std::macro_support::Poll::Ready(Ok((#(#idents.as_mut().take_output(),)*)))
+            } else {
+                ::wassup_std::macro_support::Poll::Pending
+            }
+        }).await
+    }}
+}
@@ -2,35 +2,187 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::parse_macro_input;
 
+mod export;
+mod import;
+mod join;
+mod select;
+
 #[proc_macro_attribute]
 pub fn async_main(_args: TokenStream, input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as syn::ItemFn);
-    let body = input.block;
-
-    let sig = input.sig;
-    if sig.ident.to_string() != "main"
-        || sig.asyncness.is_none()
-        || !matches!(sig.output, syn::ReturnType::Default)
-        || !sig.inputs.is_empty()
-        || sig.variadic.is_some()
-        || sig.unsafety.is_some()
-        || !sig.generics.params.is_empty()
-        || sig.constness.is_some() {
-        quote! {
-            compiler_error!("the function used with `#[wassup_std::main]` must be `async fn main() -> ()`");
+
+    if let Err(err) = check_main_signature(&input.sig) {
+        return err.to_compile_error().into();
+    }
+
+    quote! {
+        #input
+
+        #[no_mangle]
+        pub extern "C" fn _start() {
+            let _ = ::wassup_std::spawn(async {
+                ::wassup_std::startup_runtime();
+                let output = main().await;
+                ::wassup_std::exit_runtime(
+                    ::wassup_std::macro_support::MainOutput::exit_code(output),
+                );
+            });
+        }
+    }
+    .into()
+}
+
+/// `main` must be `async fn main()`, optionally returning `Result<(), E: Debug>`
+fn check_main_signature(sig: &syn::Signature) -> syn::Result<()> {
+    const USAGE: &str =
+        "the function used with `#[wassup_std::main]` must be `async fn main()` or `async fn main() -> Result<(), E>`";
+
+    if sig.ident != "main" {
+        return Err(syn::Error::new_spanned(&sig.ident, USAGE));
+    }
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(sig.fn_token, USAGE));
+    }
+    if let Some(constness) = &sig.constness {
+        return Err(syn::Error::new_spanned(constness, USAGE));
+    }
+    if let Some(unsafety) = &sig.unsafety {
+        return Err(syn::Error::new_spanned(unsafety, USAGE));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(&sig.generics, USAGE));
+    }
+    if !sig.inputs.is_empty() {
+        return Err(syn::Error::new_spanned(&sig.inputs, USAGE));
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(syn::Error::new_spanned(variadic, USAGE));
+    }
+    if let syn::ReturnType::Type(_, ty) = &sig.output {
+        let allowed = match &**ty {
+            syn::Type::Tuple(tuple) => tuple.elems.is_empty(),
+            syn::Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .map(|seg| seg.ident == "Result")
+                .unwrap_or(false),
+            _ => false,
+        };
+        if !allowed {
+            return Err(syn::Error::new_spanned(ty, USAGE));
         }
-    } else {
-        quote! {
-            #[no_mangle]
-            pub extern "C" fn _start() {
-                let _ = ::wassup_std::spawn(async {
-                    ::wassup_std::startup_runtime();
-                    async {
-                        #body
-                    }.await;
-                    ::wassup_std::shutdown_runtime();
-                });
-            }
+    }
+
+    Ok(())
+}
+
+/// Waits on multiple futures at once and runs the branch of the first one that completes.
+///
+/// ```ignore
+/// wassup_std::select! {
+///     Some(msg) = ipc.recv() => handle(msg),
+///     _ = sleep_for(Duration::from_secs(1)) => timeout(),
+/// }
+/// ```
+///
+/// Branches are polled in a rotating order, starting with `biased;` polls them top to bottom.
+/// A branch can be disabled with `, if condition` after its future, or by its pattern not matching
+/// the output. The `else` branch runs once every branch is disabled.
+#[proc_macro]
+pub fn select(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as select::Select);
+    select::expand_select(input).into()
+}
+
+/// Polls all futures concurrently and returns a tuple of their outputs.
+#[proc_macro]
+pub fn join(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as join::Join);
+    join::expand_join(input).into()
+}
+
+/// Polls all `Result` returning futures concurrently, returns the first error or a tuple of
+/// all `Ok` values.
+#[proc_macro]
+pub fn try_join(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as join::Join);
+    join::expand_try_join(input).into()
+}
+
+/// Exports an async test so the wassup test runner can find and run it in a fresh instance.
+///
+/// The test may return `()` or `Result<(), E: Debug>`, it fails by panicking or returning `Err`.
+#[proc_macro_attribute]
+pub fn test(_args: TokenStream, input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::ItemFn);
+
+    let sig = &input.sig;
+    if sig.asyncness.is_none() {
+        return syn::Error::new_spanned(sig.fn_token, "`#[wassup_std::test]` functions must be async")
+            .to_compile_error()
+            .into();
+    }
+    if !sig.inputs.is_empty() || !sig.generics.params.is_empty() || sig.variadic.is_some() {
+        return syn::Error::new_spanned(
+            &sig.inputs,
+            "`#[wassup_std::test]` functions can't take arguments or generics",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let name = &sig.ident;
+    let export = quote::format_ident!("__wassup_test_{}", name);
+
+    quote! {
+        #input
+
+        #[doc(hidden)]
+        #[export_name = concat!("__wassup_test:", module_path!(), "::", stringify!(#name))]
+        pub extern "C" fn #export() {
+            ::wassup_std::macro_support::run_test(#name);
         }
-    }.into()
+    }
+    .into()
+}
+
+/// Imports host functions registered by the embedder and generates safe wrappers for them.
+///
+/// ```ignore
+/// #[wassup_std::import(module = "kv")]
+/// extern "C" {
+///     pub fn get(key: &str, out: &mut [u8]) -> i32;
+///     pub fn counter() -> u64;
+/// }
+/// ```
+///
+/// `&str` and `&[u8]` arguments are passed to the host as a pointer and a `u32` length, other
+/// arguments and the return value must be wasm value types.
+#[proc_macro_attribute]
+pub fn import(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as import::ImportArgs);
+    let input = parse_macro_input!(input as syn::ItemForeignMod);
+    import::expand_import(args, input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Exposes an async function the host can call with `GuestInstance::call_export`.
+///
+/// ```ignore
+/// #[wassup_std::export]
+/// async fn resize(image: Image, width: u32) -> Image {
+///     // ...
+/// }
+/// ```
+///
+/// Arguments and the output are encoded with the `TypedIpc` codec, a single argument as is and
+/// several as a tuple. Every call runs as its own task on the guest runtime.
+#[proc_macro_attribute]
+pub fn export(_args: TokenStream, input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::ItemFn);
+    export::expand_export(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
 }
@@ -0,0 +1,203 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::visit_mut::VisitMut;
+use syn::{Expr, Ident, Pat, PatIdent, Token};
+
+pub struct Select {
+    biased: bool,
+    branches: Vec<Branch>,
+    else_branch: Option<Expr>,
+}
+
+struct Branch {
+    pat: Pat,
+    future: Expr,
+    condition: Option<Expr>,
+    body: Expr,
+}
+
+impl Parse for Select {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let biased = input.peek(Ident) && input.peek2(Token![;]) && {
+            let fork = input.fork();
+            fork.parse::<Ident>()? == "biased"
+        };
+        if biased {
+            input.parse::<Ident>()?;
+            input.parse::<Token![;]>()?;
+        }
+
+        let mut branches = vec![];
+        let mut else_branch = None;
+        while !input.is_empty() {
+            if input.peek(Token![else]) {
+                let else_token = input.parse::<Token![else]>()?;
+                if else_branch.is_some() {
+                    return Err(syn::Error::new(
+                        else_token.span,
+                        "`select!` can only have one `else` branch",
+                    ));
+                }
+                input.parse::<Token![=>]>()?;
+                let body = input.parse()?;
+                parse_separator(input, &body)?;
+                else_branch = Some(body);
+            } else {
+                let pat = input.parse()?;
+                input.parse::<Token![=]>()?;
+                let future = input.parse()?;
+                let condition = if input.peek(Token![,]) && input.peek2(Token![if]) {
+                    input.parse::<Token![,]>()?;
+                    input.parse::<Token![if]>()?;
+                    Some(input.parse()?)
+                } else {
+                    None
+                };
+                input.parse::<Token![=>]>()?;
+                let body = input.parse()?;
+                parse_separator(input, &body)?;
+                branches.push(Branch {
+                    pat,
+                    future,
+                    condition,
+                    body,
+                });
+            }
+        }
+
+        if branches.is_empty() {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "`select!` requires at least one branch",
+            ));
+        }
+
+        Ok(Self {
+            biased,
+            branches,
+            else_branch,
+        })
+    }
+}
+
+/// Branches are separated by commas, which are optional after a block body like in `match`.
+fn parse_separator(input: ParseStream, body: &Expr) -> syn::Result<()> {
+    if matches!(body, Expr::Block(_)) {
+        input.parse::<Option<Token![,]>>()?;
+    } else if !input.is_empty() {
+        input.parse::<Token![,]>()?;
+    }
+    Ok(())
+}
+
+/// Strips `mut` and `ref` from bindings, so the pattern can be checked against a reference
+/// before the value is moved out.
+struct CleanPattern;
+
+impl VisitMut for CleanPattern {
+    fn visit_pat_ident_mut(&mut self, pat: &mut PatIdent) {
+        pat.by_ref = None;
+        pat.mutability = None;
+        syn::visit_mut::visit_pat_ident_mut(self, pat);
+    }
+}
+
+pub fn expand_select(select: Select) -> TokenStream {
+    let count = select.branches.len();
+    let variants = (0..count)
+        .map(|i| format_ident!("_{}", i))
+        .collect::<Vec<_>>();
+    let futures = select.branches.iter().map(|branch| &branch.future);
+    let conditions = select.branches.iter().map(|branch| match &branch.condition {
+        Some(condition) => quote! { !(#condition) },
+        None => quote! { false },
+    });
+
+    let start = if select.biased {
+        quote! { 0 }
+    } else {
+        quote! { ::wassup_std::macro_support::select_start(#count) }
+    };
+
+    let poll_branches = select.branches.iter().enumerate().map(|(i, branch)| {
+        let index = syn::Index::from(i);
+        let variant = &variants[i];
+        let mut pat = branch.pat.clone();
+        CleanPattern.visit_pat_mut(&mut pat);
+        quote! {
+            #i => {
+                if __wassup_disabled[#i] {
+                    continue;
+                }
+                // SAFETY: `__wassup_futures` lives in the enclosing async block and is never moved
+                let future = unsafe { ::wassup_std::macro_support::Pin::new_unchecked(&mut __wassup_futures.#index) };
+                if let ::wassup_std::macro_support::Poll::Ready(out) =
+                    ::wassup_std::macro_support::Future::poll(future, cx)
+                {
+                    #[allow(unused_variables)]
+                    match &out {
+                        #pat => {}
+                        _ => {
+                            __wassup_disabled[#i] = true;
+                            continue;
+                        }
+                    }
+                    return ::wassup_std::macro_support::Poll::Ready(__WassupOut::#variant(out));
+                }
+                pending = true;
+            }
+        }
+    });
+
+    let arms = select.branches.iter().enumerate().map(|(i, branch)| {
+        let variant = &variants[i];
+        let pat = &branch.pat;
+        let body = &branch.body;
+        quote! {
+            __WassupOut::#variant(#pat) => #body,
+        }
+    });
+
+    let else_arm = match &select.else_branch {
+        Some(body) => quote! { __WassupOut::Disabled => #body, },
+        None => quote! {
+            __WassupOut::Disabled => panic!("all branches of `select!` are disabled and there is no `else` branch"),
+        },
+    };
+
+    quote! {{
+        #[allow(non_camel_case_types)]
+        enum __WassupOut<#(#variants,)*> {
+            #(#variants(#variants),)*
+            Disabled,
+        }
+
+        let mut __wassup_futures = (#(#futures,)*);
+        let mut __wassup_disabled: [bool; #count] = [#(#conditions,)*];
+
+        let __wassup_out = ::wassup_std::macro_support::poll_fn(|cx| {
+            let start = #start;
+            let mut pending = false;
+            for i in 0..#count {
+                match (start + i) % #count {
+                    #(#poll_branches)*
+                    _ => unreachable!(),
+                }
+            }
+            if pending {
+                ::wassup_std::macro_support::Poll::Pending
+            } else {
+                ::wassup_std::macro_support::Poll::Ready(__WassupOut::Disabled)
+            }
+        }).await;
+
+        #[allow(unreachable_code)]
+        match __wassup_out {
+            #(#arms)*
+            #else_arm
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        }
+    }}
+}